@@ -1,127 +1,299 @@
 use std::{
+    collections::{HashMap, HashSet},
     path::{Path, PathBuf},
     sync::Arc,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 use anyhow::{Context, Result};
 use clap::Parser;
 use image::{ImageFormat, RgbImage};
 use indicatif::ProgressIterator;
-use qdrant_client::qdrant::PointStruct;
+use qdrant_client::{qdrant::PointStruct, Payload as QdrantPayload};
 use uuid::Uuid;
 use walkdir::WalkDir;
 
-use image_tager::{progress_style, Config as AppConfig, QdrantWrapper, S3Client};
+use image_tager::{
+    key_hash, progress_style, to_named_vectors, Config as AppConfig, ObjectStorage, Payload,
+    QdrantWrapper, Storage, StorageLayout, UploadOptions, VectorSpec, VectorStorage,
+    SPARSE_TAGS_VECTOR, TAGS_VECTOR,
+};
 use models::WdTagger;
 
+/// Number of tags recorded in the metadata of uploaded objects.
+const TOP_TAGS_METADATA: usize = 10;
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct CliConfig {
-    input_dir: PathBuf,
+    #[arg(
+        required_unless_present = "from_bucket",
+        conflicts_with = "from_bucket"
+    )]
+    input_dir: Option<PathBuf>,
     #[arg(short, long, default_value_t = 128)]
     batch_size: usize,
     #[arg(short, long, default_value_t = 0)]
     device_id: i32,
     #[arg(short, long, default_value_t = 16)]
     num_threads: usize,
+    /// Also store tags with at least this probability as a sparse vector
+    #[arg(long)]
+    sparse_floor: Option<f32>,
+    /// Store only the sparse vector, leaving out the dense one to save space
+    #[arg(long, requires = "sparse_floor")]
+    sparse_only: bool,
+    /// Minimum probability for a tag to be recorded in the payload
+    #[arg(long, default_value_t = 0.35)]
+    tag_threshold: f32,
+    /// Index into a new collection version and switch the alias to it when done
+    #[arg(long)]
+    reindex: bool,
+    /// Index every image already in the bucket instead of a local directory; with --reindex
+    /// this rebuilds the whole index, e.g. after a model upgrade
+    #[arg(long)]
+    from_bucket: bool,
+    /// List the bucket once up front instead of sending a HEAD request per image; faster
+    /// when most images are already uploaded
+    #[arg(long, conflicts_with = "from_bucket")]
+    list_existing: bool,
+    /// Tag uploaded objects with their rating and the model id, e.g. for lifecycle rules
+    #[arg(long)]
+    object_tags: bool,
 }
 
 struct ImageProcessor {
-    s3_client: Arc<S3Client>,
+    /// `None` in index-only mode, where points reference the files where they are.
+    storage: Option<Arc<Storage>>,
     qdrant_client: Arc<QdrantWrapper>,
     model: Arc<WdTagger>,
     num_threads: usize,
+    tag_threshold: f32,
+    object_tags: bool,
+    vectors: Vec<VectorSpec>,
     app_config: AppConfig,
-    base_url: String,
+    layout: StorageLayout,
 }
 
 impl ImageProcessor {
-    fn new(device_id: i32, num_threads: usize) -> Result<Self> {
+    fn new(config: &CliConfig) -> Result<Self> {
         let app_config = AppConfig::new()?;
-        let base_url = format!("{}/{}", &app_config.s3_endpoint, &app_config.s3_bucket_name);
+        let layout = StorageLayout::new(&app_config);
+        let model = WdTagger::new(config.device_id, config.num_threads)?;
+
+        let mut vectors = Vec::new();
+        if !config.sparse_only {
+            vectors.push(VectorSpec::new(
+                TAGS_VECTOR,
+                VectorStorage::Dense {
+                    size: model.output_size as u64,
+                },
+            ));
+        }
+        if let Some(floor) = config.sparse_floor {
+            vectors.push(VectorSpec::new(
+                SPARSE_TAGS_VECTOR,
+                VectorStorage::Sparse { floor },
+            ));
+        }
 
         Ok(Self {
-            s3_client: Arc::from(S3Client::new()?),
+            storage: Storage::from_config()?.map(Arc::from),
             qdrant_client: Arc::from(QdrantWrapper::new()?),
-            model: Arc::from(WdTagger::new(device_id, num_threads)?),
-            num_threads,
+            model: Arc::from(model),
+            num_threads: config.num_threads,
+            tag_threshold: config.tag_threshold,
+            object_tags: config.object_tags,
+            vectors,
             app_config,
-            base_url,
+            layout,
         })
     }
 
-    async fn process(&self, config: &CliConfig) -> Result<()> {
-        let input_dir = self.canonicalize_input_dir(&config.input_dir)?;
-        self.ensure_image_collection_exists().await?;
+    async fn process(&mut self, config: &CliConfig) -> Result<()> {
+        let entries = match &config.input_dir {
+            Some(input_dir) => self.get_image_entries(&self.canonicalize_input_dir(input_dir)?),
+            None => self.get_bucket_entries().await?,
+        };
+
+        let alias = self.app_config.collection_name.clone();
+        let (collection, history_collection) = if config.reindex {
+            // Carry source paths over from the version being replaced
+            let previous = self.qdrant_client.alias_target(&alias).await?;
+            let collection = self.create_collection_version().await?;
+            (collection.clone(), previous.unwrap_or(collection))
+        } else {
+            self.ensure_image_collection_exists().await?;
+            (alias.clone(), alias.clone())
+        };
 
-        let entries = self.get_image_entries(&input_dir);
-        self.process_entries(entries, config.batch_size).await
+        let known_objects = match &self.storage {
+            Some(storage) if config.list_existing => {
+                Some(storage.list(None).await?.into_iter().collect())
+            }
+            _ => None,
+        };
+
+        self.process_entries(
+            entries,
+            &collection,
+            &history_collection,
+            known_objects.as_ref(),
+            config.batch_size,
+        )
+        .await?;
+
+        if config.reindex {
+            self.qdrant_client.switch_alias(&alias, &collection).await?;
+            println!("Switched {alias} to {collection}");
+        }
+        Ok(())
     }
 
     fn canonicalize_input_dir(&self, input_dir: &Path) -> Result<PathBuf> {
         dunce::canonicalize(input_dir).context("Failed to canonicalize input directory")
     }
 
-    async fn ensure_image_collection_exists(&self) -> Result<()> {
-        if self
-            .qdrant_client
-            .get_collection_info(&self.app_config.collection_name)
-            .await
-            .is_err()
-        {
-            self.qdrant_client
-                .create_collection(
-                    &self.app_config.collection_name,
-                    self.model.output_size as u64,
-                )
-                .await?;
+    /// Makes `collection_name` usable, creating a first collection version behind it if needed.
+    ///
+    /// Collections created before aliases were introduced are used as they are. Points added
+    /// to an existing collection get the vectors it already has, which is a single unnamed one
+    /// for collections older than named vectors.
+    async fn ensure_image_collection_exists(&mut self) -> Result<()> {
+        let alias = &self.app_config.collection_name;
+        if self.qdrant_client.get_collection_info(alias).await.is_err() {
+            let collection = self.create_collection_version().await?;
+            self.qdrant_client.switch_alias(alias, &collection).await?;
+            return Ok(());
+        }
+
+        let mut vectors = self.qdrant_client.collection_vectors(alias).await?;
+        for spec in vectors.iter_mut().filter(|spec| spec.is_sparse()) {
+            // The collection doesn't record the floor sparse vectors were built with
+            let requested = self
+                .vectors
+                .iter()
+                .find(|v| v.is_sparse() && v.name == spec.name)
+                .with_context(|| {
+                    format!(
+                        "{alias} has the sparse vector {:?}; pass --sparse-floor to fill it",
+                        spec.name
+                    )
+                })?;
+            spec.storage = requested.storage;
         }
+        self.vectors = vectors;
         Ok(())
     }
 
-    fn get_image_entries(&self, input_dir: &Path) -> Vec<PathBuf> {
+    async fn create_collection_version(&self) -> Result<String> {
+        let alias = &self.app_config.collection_name;
+        let collections = self.qdrant_client.list_collections().await?;
+        anyhow::ensure!(
+            !collections.contains(alias),
+            "{alias} is a plain collection; export and import it into {alias}_v1 and delete it \
+             before building versions behind an alias"
+        );
+
+        let collection = self.qdrant_client.next_collection_version(alias).await?;
+        self.qdrant_client
+            .create_collection(
+                &collection,
+                &self.vectors,
+                &self.app_config.collection_options(),
+            )
+            .await?;
+        Ok(collection)
+    }
+
+    fn get_image_entries(&self, input_dir: &Path) -> Vec<ImageSource> {
         WalkDir::new(input_dir)
             .into_iter()
             .filter_map(Result::ok)
             .map(|e| e.into_path())
             .filter(|e| ImageFormat::from_path(e).is_ok())
+            .map(ImageSource::Local)
             .collect()
     }
 
-    async fn process_entries(&self, entries: Vec<PathBuf>, batch_size: usize) -> Result<()> {
+    /// The object storage, which only indexing from the bucket can't do without.
+    fn bucket(&self) -> Result<&Storage> {
+        self.storage
+            .as_deref()
+            .context("Indexing from the bucket needs object storage to be configured")
+    }
+
+    async fn get_bucket_entries(&self) -> Result<Vec<ImageSource>> {
+        Ok(self
+            .bucket()?
+            .list(None)
+            .await?
+            .into_iter()
+            .filter(|key| ImageFormat::from_path(key).is_ok())
+            .map(ImageSource::Bucket)
+            .collect())
+    }
+
+    async fn process_entries(
+        &self,
+        entries: Vec<ImageSource>,
+        collection: &str,
+        history_collection: &str,
+        known_objects: Option<&HashSet<String>>,
+        batch_size: usize,
+    ) -> Result<()> {
         for batch in entries
             .chunks(batch_size)
             .progress_with_style(progress_style()?)
         {
             let processed_batch = self.process_batch(batch).await?;
-            self.upload_and_index_batch(processed_batch).await?;
+            self.upload_and_index_batch(
+                processed_batch,
+                collection,
+                history_collection,
+                known_objects,
+            )
+            .await?;
         }
         Ok(())
     }
 
-    async fn process_batch(&self, batch: &[PathBuf]) -> Result<Vec<ProcessedImage>> {
+    async fn process_batch(&self, batch: &[ImageSource]) -> Result<Vec<ProcessedImage>> {
         let mut processed_batch = Vec::new();
-        for paths in batch.chunks(self.num_threads) {
-            let datas = self.load_and_hash_images(paths).await?;
+        for sources in batch.chunks(self.num_threads) {
+            let datas = self.load_and_hash_images(sources).await?;
             let vectors = self
                 .model
                 .predicts(&datas.iter().map(|d| d.image.clone()).collect::<Vec<_>>())
                 .await?;
 
             processed_batch.extend(datas.into_iter().zip(vectors).map(|(data, vector)| {
+                let (width, height) = data.image.dimensions();
                 ProcessedImage {
-                    path: data.path,
+                    source: data.source,
+                    tags: self.model.tags_above(&vector, self.tag_threshold),
+                    top_tags: self.model.top_tags(&vector, TOP_TAGS_METADATA),
+                    rating: self.model.rating(&vector).map(String::from),
                     vector,
                     hash: data.hash,
+                    width,
+                    height,
                 }
             }));
         }
         Ok(processed_batch)
     }
 
-    async fn load_and_hash_images(&self, paths: &[PathBuf]) -> Result<Vec<ImageData>> {
-        futures_util::future::try_join_all(paths.iter().map(|path| self.load_and_hash_image(path)))
-            .await
+    async fn load_and_hash_images(&self, sources: &[ImageSource]) -> Result<Vec<ImageData>> {
+        futures_util::future::try_join_all(sources.iter().map(|source| match source {
+            ImageSource::Local(path) => {
+                futures_util::future::Either::Left(self.load_and_hash_image(path))
+            }
+            ImageSource::Bucket(key) => {
+                futures_util::future::Either::Right(self.download_and_hash_image(key))
+            }
+        }))
+        .await
     }
 
     async fn load_and_hash_image(&self, path: &Path) -> Result<ImageData> {
@@ -131,79 +303,272 @@ impl ImageProcessor {
                 let mut hasher = blake3::Hasher::new();
                 let hash = hasher.update_mmap(&path)?.finalize().to_string();
                 let image = image::open(&path)?.into_rgb8();
-                Ok(ImageData { path, image, hash })
+                Ok(ImageData {
+                    source: ImageSource::Local(path),
+                    image,
+                    hash,
+                })
             }
         })
-            .await?
+        .await?
+    }
+
+    async fn download_and_hash_image(&self, key: &str) -> Result<ImageData> {
+        let data = self
+            .bucket()?
+            .get(key)
+            .await
+            .with_context(|| format!("Failed to download {key}"))?;
+        tokio::task::spawn_blocking({
+            let key = key.to_owned();
+            move || -> Result<ImageData> {
+                let hash = blake3::hash(&data).to_string();
+                if key_hash(&key) != hash {
+                    eprintln!("Object {key} has content hash {hash}");
+                }
+                let image = image::load_from_memory(&data)?.into_rgb8();
+                Ok(ImageData {
+                    source: ImageSource::Bucket(key),
+                    image,
+                    hash,
+                })
+            }
+        })
+        .await?
     }
 
-    async fn upload_and_index_batch(&self, batch: Vec<ProcessedImage>) -> Result<()> {
-        let qdrant_points: Vec<_> = futures_util::future::join_all(
-            batch.into_iter().map(|img| self.prepare_qdrant_point(img)),
+    async fn upload_and_index_batch(
+        &self,
+        batch: Vec<ProcessedImage>,
+        collection: &str,
+        history_collection: &str,
+        known_objects: Option<&HashSet<String>>,
+    ) -> Result<()> {
+        let uploaded: Vec<_> = futures_util::future::try_join_all(
+            batch
+                .into_iter()
+                .map(|img| self.upload_image(img, known_objects)),
         )
-            .await;
+        .await?;
+
+        let ids: Vec<String> = uploaded
+            .iter()
+            .map(|(img, _)| point_id(&img.hash))
+            .collect();
+        let indexed_at = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |d| d.as_secs() as i64);
+        let mut payloads = self.qdrant_client.get_payloads(collection, &ids).await?;
+        if history_collection != collection {
+            let missing: Vec<String> = ids
+                .iter()
+                .filter(|id| !payloads.contains_key(*id))
+                .cloned()
+                .collect();
+            payloads.extend(
+                self.qdrant_client
+                    .get_payloads(history_collection, &missing)
+                    .await?,
+            );
+        }
+        let mut histories: HashMap<String, SourceHistory> = payloads
+            .into_iter()
+            .map(|(id, payload)| (id, SourceHistory::from_payload(payload, indexed_at)))
+            .collect();
+
+        // Files sharing content within the batch collapse into one point carrying all their paths
+        let mut qdrant_points = HashMap::new();
+        for ((img, key), id) in uploaded.into_iter().zip(ids) {
+            let history = histories
+                .entry(id.clone())
+                .or_insert_with(|| SourceHistory::new(indexed_at));
+            if let ImageSource::Local(path) = &img.source {
+                history.add(path);
+            }
+            let point = self.create_qdrant_point(img, &id, key.as_deref(), history, indexed_at);
+            qdrant_points.insert(id, point);
+        }
 
         self.qdrant_client
-            .add_points(&self.app_config.collection_name, qdrant_points)
+            .add_points(collection, qdrant_points.into_values().collect())
             .await
     }
 
-    async fn prepare_qdrant_point(&self, img: ProcessedImage) -> PointStruct {
-        let filename = format!(
-            "{}.{}",
-            img.hash,
-            img.path.extension().unwrap().to_str().unwrap()
-        );
-        let full_url = format!("{}/{}", self.base_url, filename);
+    async fn upload_image(
+        &self,
+        img: ProcessedImage,
+        known_objects: Option<&HashSet<String>>,
+    ) -> Result<(ProcessedImage, Option<String>)> {
+        let path = match &img.source {
+            ImageSource::Local(path) => path,
+            ImageSource::Bucket(key) => {
+                let key = key.clone();
+                return Ok((img, Some(key)));
+            }
+        };
+        let Some(storage) = &self.storage else {
+            return Ok((img, None));
+        };
+        let key = self.layout.key_for_path(&img.hash, path)?;
 
-        // Upload file to S3 if it doesn't exist
-        if let Err(e) = self.upload_to_s3_if_not_exists(&img.path, &filename).await {
-            eprintln!("Failed to upload file to S3: {}", e);
-        }
+        // A point must never reference an object that wasn't stored
+        self.upload_if_not_exists(storage, &img, path, &key, known_objects)
+            .await
+            .with_context(|| format!("Failed to upload {} as {key}", path.display()))?;
 
-        self.create_qdrant_point(
-            &img.hash,
-            img.vector,
-            img.path.file_name().unwrap().to_str().unwrap(),
-            &full_url,
-        )
+        Ok((img, Some(key)))
     }
 
-    async fn upload_to_s3_if_not_exists(&self, path: &Path, filename: &str) -> Result<()> {
-        if !self.s3_client.search_file(filename).await? {
-            let data = tokio::fs::read(path).await?;
-            self.s3_client.upload_file(filename, &data).await?;
+    async fn upload_if_not_exists(
+        &self,
+        storage: &Storage,
+        img: &ProcessedImage,
+        path: &Path,
+        filename: &str,
+        known_objects: Option<&HashSet<String>>,
+    ) -> Result<()> {
+        let exists = match known_objects {
+            Some(known) => known.contains(filename),
+            None => storage.exists(filename).await?,
+        };
+        if !exists {
+            storage
+                .put_path(filename, path, &self.upload_options(img, path))
+                .await?;
         }
         Ok(())
     }
 
+    fn upload_options(&self, img: &ProcessedImage, path: &Path) -> UploadOptions {
+        let mut options = UploadOptions {
+            metadata: HashMap::from([
+                ("filename".to_string(), file_name(path).to_string()),
+                ("width".to_string(), img.width.to_string()),
+                ("height".to_string(), img.height.to_string()),
+                ("tags".to_string(), img.top_tags.join(",")),
+                ("model".to_string(), self.model.id().to_string()),
+            ]),
+            ..Default::default()
+        };
+        if self.object_tags {
+            options
+                .tags
+                .push(("model".to_string(), self.model.id().to_string()));
+            if let Some(rating) = &img.rating {
+                options.tags.push(("rating".to_string(), rating.clone()));
+            }
+        }
+        options
+    }
+
     fn create_qdrant_point(
         &self,
-        hash: &str,
-        vector: Vec<f32>,
-        path_str: &str,
-        full_url: &str,
+        img: ProcessedImage,
+        id: &str,
+        key: Option<&str>,
+        history: &SourceHistory,
+        indexed_at: i64,
     ) -> PointStruct {
+        // Objects from the bucket are named after their latest known source, if any
+        let (path, path_str) = match (&img.source, history.paths.last()) {
+            (ImageSource::Local(path), _) => (Some(path.as_path()), file_name(path)),
+            (ImageSource::Bucket(_), Some(last)) => (Some(Path::new(last)), file_name(last)),
+            (ImageSource::Bucket(key), None) => (None, key.as_str()),
+        };
+        let mut payload = QdrantPayload::from([
+            ("path", path_str.into()),
+            ("hash", img.hash.as_str().into()),
+            ("tags", img.tags.into()),
+            ("rating", img.rating.unwrap_or_default().into()),
+            ("width", i64::from(img.width).into()),
+            ("height", i64::from(img.height).into()),
+            ("indexed_at", indexed_at.into()),
+            ("paths", history.paths.clone().into()),
+            ("first_seen", history.first_seen.into()),
+            ("last_seen", indexed_at.into()),
+        ]);
+        if let Some(source_folder) = path.and_then(Path::parent).and_then(Path::to_str) {
+            payload.insert("source_folder", source_folder);
+        }
+        // Index-only points are found again through their absolute `paths`
+        if let Some(key) = key {
+            payload.insert("key", key);
+        }
         PointStruct::new(
-            Uuid::new_v5(&Uuid::NAMESPACE_DNS, hash.as_ref()).to_string(),
-            vector,
-            [
-                ("path", path_str.into()),
-                ("hash", hash.into()),
-                ("url", full_url.into()),
-            ],
+            id.to_string(),
+            to_named_vectors(&self.vectors, &img.vector),
+            payload,
         )
     }
 }
 
+fn point_id(hash: &str) -> String {
+    Uuid::new_v5(&Uuid::NAMESPACE_DNS, hash.as_bytes()).to_string()
+}
+
+/// Every location a piece of content was indexed from, merged across runs.
+struct SourceHistory {
+    paths: Vec<String>,
+    first_seen: i64,
+}
+
+impl SourceHistory {
+    fn new(now: i64) -> Self {
+        Self {
+            paths: Vec::new(),
+            first_seen: now,
+        }
+    }
+
+    fn from_payload(payload: Payload, now: i64) -> Self {
+        let mut paths = payload.paths;
+        // Points indexed before paths were tracked only know their latest location
+        if paths.is_empty() {
+            if let (Some(folder), Some(name)) = (payload.source_folder, payload.path) {
+                paths.push(Path::new(&folder).join(name).to_string_lossy().into_owned());
+            }
+        }
+        Self {
+            paths,
+            first_seen: payload.first_seen.or(payload.indexed_at).unwrap_or(now),
+        }
+    }
+
+    fn add(&mut self, path: &Path) {
+        let path = path.to_string_lossy().into_owned();
+        if !self.paths.contains(&path) {
+            self.paths.push(path);
+        }
+    }
+}
+
+fn file_name(path: &(impl AsRef<Path> + ?Sized)) -> &str {
+    path.as_ref().file_name().unwrap().to_str().unwrap()
+}
+
+/// Where an image to index comes from.
+#[derive(Clone)]
+enum ImageSource {
+    /// A local file, uploaded to the bucket if missing.
+    Local(PathBuf),
+    /// An object already in the bucket, by key.
+    Bucket(String),
+}
+
 struct ProcessedImage {
-    path: PathBuf,
+    source: ImageSource,
     vector: Vec<f32>,
     hash: String,
+    tags: Vec<String>,
+    /// Best tags first, for object metadata.
+    top_tags: Vec<String>,
+    rating: Option<String>,
+    width: u32,
+    height: u32,
 }
 
 struct ImageData {
-    path: PathBuf,
+    source: ImageSource,
     image: RgbImage,
     hash: String,
 }
@@ -211,6 +576,6 @@ struct ImageData {
 #[tokio::main]
 async fn main() -> Result<()> {
     let config = CliConfig::parse();
-    let processor = ImageProcessor::new(config.device_id, config.num_threads)?;
+    let mut processor = ImageProcessor::new(&config)?;
     processor.process(&config).await
 }
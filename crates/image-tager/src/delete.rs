@@ -0,0 +1,121 @@
+use std::collections::{HashMap, HashSet};
+
+use anyhow::{Context, Result};
+use serde::Serialize;
+
+use crate::{ObjectStorage, PayloadFilter, PointRef, QdrantWrapper, Storage, StoredPoint};
+
+/// Selects the points to delete.
+pub enum DeleteTarget {
+    Refs(Vec<PointRef>),
+    Filter(PayloadFilter),
+}
+
+/// Points and objects a deletion removes, worked out before anything is touched.
+#[derive(Serialize, Debug)]
+pub struct DeletePlan {
+    pub collection: String,
+    pub points: Vec<StoredPoint>,
+    /// Objects referenced only by the deleted points.
+    pub objects: Vec<String>,
+    /// Objects left in place because other points, in any collection, still reference them.
+    pub kept_objects: Vec<String>,
+}
+
+impl DeletePlan {
+    pub async fn new(
+        qdrant: &QdrantWrapper,
+        collection: &str,
+        target: &DeleteTarget,
+    ) -> Result<Self> {
+        let mut ids = match target {
+            DeleteTarget::Refs(refs) => {
+                let mut ids = Vec::new();
+                for point_ref in refs {
+                    ids.extend(qdrant.resolve_point_ref(collection, point_ref).await?);
+                }
+                ids
+            }
+            DeleteTarget::Filter(filter) => qdrant.find_point_ids(collection, filter).await?,
+        };
+        ids.sort_unstable();
+        ids.dedup();
+
+        // Ids given directly may not exist; only keep the points actually found
+        let mut payloads = qdrant.get_payloads(collection, &ids).await?;
+        let points: Vec<StoredPoint> = ids
+            .into_iter()
+            .filter_map(|id| {
+                let payload = payloads.remove(&id)?;
+                Some(StoredPoint {
+                    id,
+                    vectors: HashMap::new(),
+                    payload,
+                })
+            })
+            .collect();
+        let ids: Vec<String> = points.iter().map(|point| point.id.clone()).collect();
+
+        let resolved = qdrant
+            .alias_target(collection)
+            .await?
+            .unwrap_or_else(|| collection.to_string());
+        let collections = qdrant.list_collections().await?;
+
+        let mut objects = Vec::new();
+        let mut kept_objects = Vec::new();
+        let mut seen = HashSet::new();
+        for point in &points {
+            // Images indexed in place have no object to delete
+            let Some(key) = point.payload.object_key() else {
+                continue;
+            };
+            if !seen.insert(key) {
+                continue;
+            }
+            let referenced = match &point.payload.hash {
+                Some(hash) => {
+                    let mut referenced = false;
+                    for other in &collections {
+                        let excluding = if *other == resolved { &ids[..] } else { &[] };
+                        if qdrant.count_hash_references(other, hash, excluding).await? > 0 {
+                            referenced = true;
+                            break;
+                        }
+                    }
+                    referenced
+                }
+                // Without a hash other references can't be ruled out
+                None => true,
+            };
+            if referenced {
+                kept_objects.push(key.to_string());
+            } else {
+                objects.push(key.to_string());
+            }
+        }
+
+        Ok(Self {
+            collection: collection.to_string(),
+            points,
+            objects,
+            kept_objects,
+        })
+    }
+
+    /// Deletes the points, then the objects no other point references.
+    pub async fn execute(&self, qdrant: &QdrantWrapper, storage: Option<&Storage>) -> Result<()> {
+        let ids: Vec<String> = self.points.iter().map(|point| point.id.clone()).collect();
+        qdrant.delete_points(&self.collection, &ids).await?;
+        let Some(storage) = storage else {
+            return Ok(());
+        };
+        for key in &self.objects {
+            storage
+                .delete(key)
+                .await
+                .with_context(|| format!("Failed to delete object {key}"))?;
+        }
+        Ok(())
+    }
+}
@@ -1,23 +1,53 @@
+use std::path::PathBuf;
+
 use anyhow::Result;
 use dotenvy::dotenv;
 use indicatif::ProgressStyle;
 use serde::Deserialize;
 
+pub use crate::delete::*;
 pub use crate::qdrant_wrapper::*;
 pub use crate::s3client::*;
+pub use crate::storage::*;
+pub use crate::storage_layout::*;
 
+mod delete;
 mod qdrant_wrapper;
 mod s3client;
+mod storage;
+mod storage_layout;
 
 #[derive(Deserialize, Debug)]
 pub struct Config {
-    pub aws_access_key_id: String,
-    pub aws_secret_access_key: String,
-    pub aws_region: String,
-    pub s3_bucket_name: String,
-    pub s3_endpoint: String,
+    /// The S3 settings are only required by the S3 storage backend.
+    pub aws_access_key_id: Option<String>,
+    pub aws_secret_access_key: Option<String>,
+    pub aws_region: Option<String>,
+    pub s3_bucket_name: Option<String>,
+    pub s3_endpoint: Option<String>,
+    /// Store objects under `ab/cd/` prefixes taken from their hash.
+    pub s3_key_sharding: Option<bool>,
+    /// Files at least this many bytes are uploaded in parts; 64 MiB when unset.
+    pub s3_multipart_threshold: Option<u64>,
+    /// Lifetime of presigned URLs in seconds; one hour when unset.
+    pub s3_presign_expiry: Option<u64>,
+    /// `s3`, `local` or `none`; defaults to `s3` when a bucket is configured and to `none`
+    /// otherwise.
+    pub storage_backend: Option<StorageBackend>,
+    /// Directory holding the objects of the local storage backend.
+    pub local_storage_dir: Option<PathBuf>,
     pub qdrant_url: String,
+    /// REST endpoint used for snapshot transfers; derived from `qdrant_url` when unset.
+    pub qdrant_rest_url: Option<String>,
     pub collection_name: String,
+    pub vector_distance: Option<VectorDistance>,
+    pub hnsw_m: Option<u64>,
+    pub hnsw_ef_construct: Option<u64>,
+    pub quantization: Option<Quantization>,
+    pub on_disk_vectors: Option<bool>,
+    pub on_disk_payload: Option<bool>,
+    pub shard_number: Option<u32>,
+    pub replication_factor: Option<u32>,
 }
 
 impl Config {
@@ -28,6 +58,36 @@ impl Config {
             .build()?
             .try_deserialize()?)
     }
+
+    /// The REST endpoint, falling back to `qdrant_url` with the default gRPC port 6334
+    /// swapped for the REST port 6333.
+    pub fn qdrant_rest_url(&self) -> String {
+        self.qdrant_rest_url
+            .clone()
+            .unwrap_or_else(|| self.qdrant_url.replace(":6334", ":6333"))
+    }
+
+    pub fn storage_backend(&self) -> StorageBackend {
+        self.storage_backend
+            .unwrap_or(if self.s3_bucket_name.is_some() {
+                StorageBackend::S3
+            } else {
+                StorageBackend::None
+            })
+    }
+
+    pub fn collection_options(&self) -> CollectionOptions {
+        CollectionOptions {
+            distance: self.vector_distance.unwrap_or_default(),
+            hnsw_m: self.hnsw_m,
+            hnsw_ef_construct: self.hnsw_ef_construct,
+            quantization: self.quantization,
+            on_disk_vectors: self.on_disk_vectors,
+            on_disk_payload: self.on_disk_payload,
+            shard_number: self.shard_number,
+            replication_factor: self.replication_factor,
+        }
+    }
 }
 
 pub fn progress_style() -> Result<ProgressStyle> {
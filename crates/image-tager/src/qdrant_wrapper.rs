@@ -1,14 +1,31 @@
-use anyhow::Result;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{Context, Result};
+use qdrant_client::qdrant::group_id::Kind as GroupIdKind;
+use qdrant_client::qdrant::point_id::PointIdOptions;
+use qdrant_client::qdrant::vectors::VectorsOptions;
+use qdrant_client::qdrant::vectors_config::Config as VectorsConfigOptions;
 use qdrant_client::qdrant::{
-    CreateCollectionBuilder, Distance, PointStruct, RecommendExample, RecommendPointsBuilder,
-    ScoredPoint, SearchParamsBuilder, UpsertPointsBuilder, VectorParamsBuilder,
+    BinaryQuantizationBuilder, Condition, CountPointsBuilder, CreateCollectionBuilder,
+    CreateFieldIndexCollectionBuilder, DeletePayloadPointsBuilder, DeletePointsBuilder, Distance,
+    FieldType, Filter, GetPointsBuilder, HnswConfigDiffBuilder, NamedVectors, PointGroup, PointId,
+    PointStruct, PointsIdsList, Range, RecommendBatchPointsBuilder, RecommendExample,
+    RecommendPointGroups, RecommendPointsBuilder, RecommendStrategy, RetrievedPoint,
+    ScalarQuantizationBuilder, ScoredPoint, ScrollPointsBuilder, SearchParamsBuilder,
+    SetPayloadPointsBuilder, SnapshotDescription, SnapshotDownloadBuilder,
+    SparseIndexConfigBuilder, SparseVectorParamsBuilder, SparseVectorsConfigBuilder,
+    UpsertPointsBuilder, Vector, VectorParamsBuilder, VectorsConfigBuilder,
 };
-use qdrant_client::Qdrant;
+use qdrant_client::{Payload as QdrantPayload, Qdrant};
+use serde::{Deserialize, Serialize};
 
-use crate::Config;
+use crate::{key_from_url, Config};
 
 pub struct QdrantWrapper {
     client: Qdrant,
+    rest_url: String,
 }
 
 impl QdrantWrapper {
@@ -17,7 +34,10 @@ impl QdrantWrapper {
         let client = Qdrant::from_url(&app_config.qdrant_url)
             .timeout(std::time::Duration::from_secs(60))
             .build()?;
-        Ok(Self { client })
+        Ok(Self {
+            client,
+            rest_url: app_config.qdrant_rest_url(),
+        })
     }
 
     // Collection operations
@@ -26,11 +46,78 @@ impl QdrantWrapper {
         Ok(response.collections.into_iter().map(|c| c.name).collect())
     }
 
-    pub async fn create_collection(&self, name: &str, vector_size: u64) -> Result<()> {
-        let config = VectorParamsBuilder::new(vector_size, Distance::Cosine);
-        self.client
-            .create_collection(CreateCollectionBuilder::new(name).vectors_config(config))
-            .await?;
+    pub async fn create_collection(
+        &self,
+        name: &str,
+        vectors: &[VectorSpec],
+        options: &CollectionOptions,
+    ) -> Result<()> {
+        let mut dense = VectorsConfigBuilder::default();
+        let mut sparse = SparseVectorsConfigBuilder::default();
+        for spec in vectors {
+            match spec.storage {
+                VectorStorage::Dense { size } => {
+                    let mut params = VectorParamsBuilder::new(size, options.distance.into());
+                    if let Some(on_disk) = options.on_disk_vectors {
+                        params = params.on_disk(on_disk);
+                    }
+                    dense.add_named_vector_params(&spec.name, params);
+                }
+                VectorStorage::Sparse { .. } => {
+                    let mut params = SparseVectorParamsBuilder::default();
+                    if let Some(on_disk) = options.on_disk_vectors {
+                        params = params.index(SparseIndexConfigBuilder::default().on_disk(on_disk));
+                    }
+                    sparse.add_named_vector_params(&spec.name, params);
+                }
+            }
+        }
+
+        let mut hnsw = HnswConfigDiffBuilder::default();
+        if let Some(m) = options.hnsw_m {
+            hnsw = hnsw.m(m);
+        }
+        if let Some(ef_construct) = options.hnsw_ef_construct {
+            hnsw = hnsw.ef_construct(ef_construct);
+        }
+
+        let mut builder = CreateCollectionBuilder::new(name).hnsw_config(hnsw);
+        if let Some(on_disk_payload) = options.on_disk_payload {
+            builder = builder.on_disk_payload(on_disk_payload);
+        }
+        if vectors.iter().any(|spec| !spec.is_sparse()) {
+            builder = builder.vectors_config(dense);
+        }
+        if vectors.iter().any(VectorSpec::is_sparse) {
+            builder = builder.sparse_vectors_config(sparse);
+        }
+        match options.quantization {
+            Some(Quantization::Scalar) => {
+                builder = builder.quantization_config(ScalarQuantizationBuilder::default());
+            }
+            Some(Quantization::Binary) => {
+                builder = builder.quantization_config(BinaryQuantizationBuilder::new(false));
+            }
+            None => {}
+        }
+        if let Some(shard_number) = options.shard_number {
+            builder = builder.shard_number(shard_number);
+        }
+        if let Some(replication_factor) = options.replication_factor {
+            builder = builder.replication_factor(replication_factor);
+        }
+        self.client.create_collection(builder).await?;
+        self.create_payload_indexes(name).await
+    }
+
+    pub async fn create_payload_indexes(&self, name: &str) -> Result<()> {
+        for (field, field_type) in PAYLOAD_INDEXES {
+            self.client
+                .create_field_index(
+                    CreateFieldIndexCollectionBuilder::new(name, *field, *field_type).wait(true),
+                )
+                .await?;
+        }
         Ok(())
     }
 
@@ -44,6 +131,184 @@ impl QdrantWrapper {
         Ok(format!("{info:#?}"))
     }
 
+    /// Reads the vectors a collection was created with.
+    ///
+    /// Qdrant doesn't keep the floor of sparse vectors, so it is reported as zero.
+    pub async fn collection_vectors(&self, name: &str) -> Result<Vec<VectorSpec>> {
+        let params = self
+            .client
+            .collection_info(name)
+            .await?
+            .result
+            .and_then(|info| info.config)
+            .and_then(|config| config.params)
+            .with_context(|| format!("Collection {name} has no parameters"))?;
+
+        let mut specs = match params.vectors_config.and_then(|c| c.config) {
+            Some(VectorsConfigOptions::Params(params)) => {
+                vec![VectorSpec::new(
+                    "",
+                    VectorStorage::Dense { size: params.size },
+                )]
+            }
+            Some(VectorsConfigOptions::ParamsMap(map)) => map
+                .map
+                .into_iter()
+                .map(|(name, params)| {
+                    VectorSpec::new(name, VectorStorage::Dense { size: params.size })
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+        specs.extend(params.sparse_vectors_config.into_iter().flat_map(|config| {
+            config
+                .map
+                .into_keys()
+                .map(|name| VectorSpec::new(name, VectorStorage::Sparse { floor: 0.0 }))
+        }));
+        Ok(specs)
+    }
+
+    // Alias operations
+    /// Collection an alias points to, or `None` if there is no such alias.
+    pub async fn alias_target(&self, alias: &str) -> Result<Option<String>> {
+        let response = self.client.list_aliases().await?;
+        Ok(response
+            .aliases
+            .into_iter()
+            .find(|description| description.alias_name == alias)
+            .map(|description| description.collection_name))
+    }
+
+    /// Points `alias` at `collection`, replacing any previous target in a single request
+    /// so that searches never see a missing alias.
+    pub async fn switch_alias(&self, alias: &str, collection: &str) -> Result<()> {
+        let mut actions = Vec::new();
+        if self.alias_target(alias).await?.is_some() {
+            actions.push(serde_json::json!({ "delete_alias": { "alias_name": alias } }));
+        }
+        actions.push(serde_json::json!({
+            "create_alias": { "collection_name": collection, "alias_name": alias }
+        }));
+
+        reqwest::Client::new()
+            .post(format!(
+                "{}/collections/aliases",
+                self.rest_url.trim_end_matches('/')
+            ))
+            .json(&serde_json::json!({ "actions": actions }))
+            .send()
+            .await?
+            .error_for_status()
+            .with_context(|| format!("Failed to point alias {alias} at {collection}"))?;
+        Ok(())
+    }
+
+    /// Versioned collections built behind `alias`, oldest first.
+    pub async fn collection_versions(&self, alias: &str) -> Result<Vec<String>> {
+        let mut versions: Vec<(u32, String)> = self
+            .list_collections()
+            .await?
+            .into_iter()
+            .filter_map(|name| Some((parse_collection_version(alias, &name)?, name)))
+            .collect();
+        versions.sort_unstable();
+        Ok(versions.into_iter().map(|(_, name)| name).collect())
+    }
+
+    /// Name for the next versioned collection behind `alias`.
+    pub async fn next_collection_version(&self, alias: &str) -> Result<String> {
+        let latest = self
+            .collection_versions(alias)
+            .await?
+            .last()
+            .and_then(|name| parse_collection_version(alias, name))
+            .unwrap_or(0);
+        Ok(format!("{alias}_v{}", latest + 1))
+    }
+
+    // Snapshot operations
+    pub async fn create_snapshot(&self, collection_name: &str) -> Result<SnapshotInfo> {
+        let response = self.client.create_snapshot(collection_name).await?;
+        response
+            .snapshot_description
+            .map(SnapshotInfo::from)
+            .context("Qdrant returned no snapshot description")
+    }
+
+    /// Snapshots of a collection, newest first.
+    pub async fn list_snapshots(&self, collection_name: &str) -> Result<Vec<SnapshotInfo>> {
+        let response = self.client.list_snapshots(collection_name).await?;
+        let mut snapshots: Vec<SnapshotInfo> = response
+            .snapshot_descriptions
+            .into_iter()
+            .map(SnapshotInfo::from)
+            .collect();
+        snapshots.sort_by_key(|snapshot| std::cmp::Reverse(snapshot.created_at));
+        Ok(snapshots)
+    }
+
+    /// Downloads a snapshot over the REST API; without a name the newest one is used.
+    pub async fn download_snapshot(
+        &self,
+        collection_name: &str,
+        snapshot_name: Option<&str>,
+        out_path: &Path,
+    ) -> Result<()> {
+        let snapshot_name = match snapshot_name {
+            Some(name) => name.to_string(),
+            None => self
+                .list_snapshots(collection_name)
+                .await?
+                .into_iter()
+                .next()
+                .map(|snapshot| snapshot.name)
+                .with_context(|| format!("Collection {collection_name} has no snapshots"))?,
+        };
+        self.client
+            .download_snapshot(
+                SnapshotDownloadBuilder::new(out_path, collection_name)
+                    .snapshot_name(snapshot_name)
+                    .rest_api_uri(&self.rest_url),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Uploads a snapshot file over the REST API, replacing the collection's data.
+    pub async fn restore_snapshot(
+        &self,
+        collection_name: &str,
+        snapshot_path: &Path,
+    ) -> Result<()> {
+        let file = tokio::fs::File::open(snapshot_path)
+            .await
+            .context("Failed to open snapshot file")?;
+        let length = file.metadata().await?.len();
+        let file_name = snapshot_path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .unwrap_or("collection.snapshot")
+            .to_string();
+        let form = reqwest::multipart::Form::new().part(
+            "snapshot",
+            reqwest::multipart::Part::stream_with_length(file, length).file_name(file_name),
+        );
+
+        reqwest::Client::new()
+            .post(format!(
+                "{}/collections/{collection_name}/snapshots/upload",
+                self.rest_url.trim_end_matches('/')
+            ))
+            .query(&[("priority", "snapshot"), ("wait", "true")])
+            .multipart(form)
+            .send()
+            .await?
+            .error_for_status()
+            .context("Qdrant rejected the snapshot")?;
+        Ok(())
+    }
+
     // Point operations
     pub async fn add_points(&self, collection_name: &str, points: Vec<PointStruct>) -> Result<()> {
         self.client
@@ -55,35 +320,331 @@ impl QdrantWrapper {
         Ok(())
     }
 
+    /// Payloads of the given points by id; ids not in the collection are left out.
+    pub async fn get_payloads(
+        &self,
+        collection_name: &str,
+        ids: &[String],
+    ) -> Result<HashMap<String, Payload>> {
+        if ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let response = self
+            .client
+            .get_points(
+                GetPointsBuilder::new(
+                    collection_name,
+                    ids.iter()
+                        .map(|id| point_id_from_str(id))
+                        .collect::<Vec<_>>(),
+                )
+                .with_payload(true),
+            )
+            .await?;
+        response
+            .result
+            .into_iter()
+            .map(|point| {
+                let stored = Self::convert_to_stored_point(point)?;
+                Ok((stored.id, stored.payload))
+            })
+            .collect()
+    }
+
+    pub async fn delete_points(&self, collection_name: &str, ids: &[String]) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        self.client
+            .delete_points(
+                DeletePointsBuilder::new(collection_name)
+                    .points(PointsIdsList {
+                        ids: ids.iter().map(|id| point_id_from_str(id)).collect(),
+                    })
+                    .wait(true),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Merges `payload` into the payloads of the points `ids`.
+    pub async fn update_payload(
+        &self,
+        collection_name: &str,
+        ids: &[String],
+        payload: QdrantPayload,
+    ) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        self.client
+            .set_payload(
+                SetPayloadPointsBuilder::new(collection_name, payload)
+                    .points_selector(PointsIdsList {
+                        ids: ids.iter().map(|id| point_id_from_str(id)).collect(),
+                    })
+                    .wait(true),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Removes the payload fields `keys` from the points `ids`.
+    pub async fn delete_payload_keys(
+        &self,
+        collection_name: &str,
+        ids: &[String],
+        keys: &[&str],
+    ) -> Result<()> {
+        if ids.is_empty() {
+            return Ok(());
+        }
+        self.client
+            .delete_payload(
+                DeletePayloadPointsBuilder::new(
+                    collection_name,
+                    keys.iter().map(|key| key.to_string()).collect::<Vec<_>>(),
+                )
+                .points_selector(PointsIdsList {
+                    ids: ids.iter().map(|id| point_id_from_str(id)).collect(),
+                })
+                .wait(true),
+            )
+            .await?;
+        Ok(())
+    }
+
+    /// Ids of every point matching `filter`; an empty filter is rejected rather than
+    /// matching the whole collection.
+    pub async fn find_point_ids(
+        &self,
+        collection_name: &str,
+        filter: &PayloadFilter,
+    ) -> Result<Vec<String>> {
+        let filter = filter
+            .to_filter()
+            .context("Refusing to select points with an empty filter")?;
+        self.scroll_ids(collection_name, filter).await
+    }
+
+    /// Ids of every point matching `filter`, across all scroll pages.
+    async fn scroll_ids(&self, collection_name: &str, filter: Filter) -> Result<Vec<String>> {
+        let mut ids = Vec::new();
+        let mut offset: Option<PointId> = None;
+        loop {
+            let mut builder = ScrollPointsBuilder::new(collection_name)
+                .filter(filter.clone())
+                .with_payload(false)
+                .limit(256);
+            if let Some(offset) = offset.take() {
+                builder = builder.offset(offset);
+            }
+            let response = self.client.scroll(builder).await?;
+            for point in &response.result {
+                ids.push(point_id_to_string(point.id.as_ref())?);
+            }
+            match response.next_page_offset {
+                Some(next) => offset = Some(next),
+                None => return Ok(ids),
+            }
+        }
+    }
+
+    /// Number of points holding content `hash`, leaving out the points in `excluding`.
+    pub async fn count_hash_references(
+        &self,
+        collection_name: &str,
+        hash: &str,
+        excluding: &[String],
+    ) -> Result<u64> {
+        let mut filter = Filter::must([Condition::matches("hash", hash.to_string())]);
+        if !excluding.is_empty() {
+            filter.must_not.push(Condition::has_id(
+                excluding.iter().map(|id| point_id_from_str(id)),
+            ));
+        }
+        let response = self
+            .client
+            .count(
+                CountPointsBuilder::new(collection_name)
+                    .filter(filter)
+                    .exact(true),
+            )
+            .await?;
+        Ok(response.result.map_or(0, |result| result.count))
+    }
+
+    pub async fn count_points(&self, collection_name: &str) -> Result<u64> {
+        let response = self
+            .client
+            .count(CountPointsBuilder::new(collection_name).exact(true))
+            .await?;
+        Ok(response.result.map_or(0, |result| result.count))
+    }
+
+    /// Reads one page of points, starting at `offset` or at the beginning of the collection.
+    pub async fn scroll_points(
+        &self,
+        collection_name: &str,
+        offset: Option<&str>,
+        limit: u32,
+        with_vectors: bool,
+    ) -> Result<ScrollPage> {
+        let mut builder = ScrollPointsBuilder::new(collection_name)
+            .limit(limit)
+            .with_payload(true)
+            .with_vectors(with_vectors);
+        if let Some(offset) = offset {
+            builder = builder.offset(point_id_from_str(offset));
+        }
+        let response = self.client.scroll(builder).await?;
+
+        Ok(ScrollPage {
+            points: response
+                .result
+                .into_iter()
+                .map(Self::convert_to_stored_point)
+                .collect::<Result<_>>()?,
+            next_offset: response
+                .next_page_offset
+                .map(|id| point_id_to_string(Some(&id)))
+                .transpose()?,
+        })
+    }
+
+    /// Payloads of every point in the collection, by point id.
+    pub async fn all_payloads(&self, collection_name: &str) -> Result<HashMap<String, Payload>> {
+        let mut payloads = HashMap::new();
+        let mut offset = None;
+        loop {
+            let page = self
+                .scroll_points(collection_name, offset.as_deref(), 256, false)
+                .await?;
+            payloads.extend(page.points.into_iter().map(|p| (p.id, p.payload)));
+            match page.next_offset {
+                Some(next) => offset = Some(next),
+                None => return Ok(payloads),
+            }
+        }
+    }
+
     pub async fn search_points(
         &self,
         collection_name: &str,
-        vector: Vec<Vec<f32>>,
+        query: SearchQuery,
         search_params: &SearchParams,
-    ) -> Result<Vec<Payload>> {
-        let builder = self.build_search_builder(collection_name, vector, search_params);
+    ) -> Result<Vec<SearchHit>> {
+        let builder = self.build_search_builder(collection_name, query, search_params);
         let search_result = self.client.recommend(builder).await?;
 
-        Ok(search_result
+        search_result
+            .result
+            .iter()
+            .map(Self::convert_to_search_hit)
+            .collect()
+    }
+
+    /// Ids of the points a reference matches; a hash or path may match any number of points.
+    pub async fn resolve_point_ref(
+        &self,
+        collection_name: &str,
+        point_ref: &PointRef,
+    ) -> Result<Vec<String>> {
+        let filter = match point_ref {
+            PointRef::Id(id) => return Ok(vec![id.clone()]),
+            PointRef::Hash(hash) => Filter::must([Condition::matches("hash", hash.clone())]),
+            // Either the latest file name or any absolute source path
+            PointRef::Path(path) => Filter::should([
+                Condition::matches("path", path.clone()),
+                Condition::matches("paths", path.clone()),
+            ]),
+        };
+        self.scroll_ids(collection_name, filter).await
+    }
+
+    /// Runs one recommendation per query in a single request, returning hits in query order.
+    pub async fn search_points_batch(
+        &self,
+        collection_name: &str,
+        queries: Vec<SearchQuery>,
+        search_params: &SearchParams,
+    ) -> Result<Vec<Vec<SearchHit>>> {
+        if queries.is_empty() {
+            return Ok(Vec::new());
+        }
+        let recommend_points: Vec<_> = queries
+            .into_iter()
+            .map(|query| {
+                self.build_search_builder(collection_name, query, search_params)
+                    .build()
+            })
+            .collect();
+        let response = self
+            .client
+            .recommend_batch(RecommendBatchPointsBuilder::new(
+                collection_name,
+                recommend_points,
+            ))
+            .await?;
+
+        response
+            .result
+            .iter()
+            .map(|batch| {
+                batch
+                    .result
+                    .iter()
+                    .map(Self::convert_to_search_hit)
+                    .collect()
+            })
+            .collect()
+    }
+
+    /// Like [`Self::search_points`], but returns up to `params.limit` groups of hits
+    /// sharing a value of `group_by.field`.
+    pub async fn search_groups(
+        &self,
+        collection_name: &str,
+        query: SearchQuery,
+        search_params: &SearchParams,
+        group_by: &GroupBy,
+    ) -> Result<Vec<SearchGroup>> {
+        let request =
+            self.build_group_search_request(collection_name, query, search_params, group_by);
+        let response = self.client.recommend_groups(request).await?;
+
+        response
             .result
+            .map(|result| result.groups)
+            .unwrap_or_default()
             .iter()
-            .map(Self::convert_to_point_struct)
-            .collect())
+            .map(Self::convert_to_search_group)
+            .collect()
     }
 
     // Helper methods
     fn build_search_builder(
         &self,
         collection_name: &str,
-        vector: Vec<Vec<f32>>,
+        query: SearchQuery,
         params: &SearchParams,
     ) -> RecommendPointsBuilder {
-        vector
+        let builder = query.positive.into_iter().fold(
+            RecommendPointsBuilder::new(collection_name, params.limit),
+            |builder, example| builder.add_positive(example.into_recommend(&params.vector)),
+        );
+        let builder = query
+            .negative
             .into_iter()
-            .fold(
-                RecommendPointsBuilder::new(collection_name, params.limit),
-                |builder, vec| builder.add_positive(RecommendExample::from(vec)),
-            )
+            .fold(builder, |builder, example| {
+                builder.add_negative(example.into_recommend(&params.vector))
+            });
+        let builder = if params.vector.name.is_empty() {
+            builder
+        } else {
+            builder.using(&params.vector.name)
+        };
+        let builder = builder
             .with_payload(true)
             .params(
                 SearchParamsBuilder::default()
@@ -91,29 +652,593 @@ impl QdrantWrapper {
                     .hnsw_ef(params.hnsw_ef),
             )
             .score_threshold(params.score_threshold)
+            .strategy(RecommendStrategy::from(params.strategy));
+        match params.filter.to_filter() {
+            Some(filter) => builder.filter(filter),
+            None => builder,
+        }
+    }
+
+    /// The request of [`Self::build_search_builder`], grouped by `group_by.field`.
+    fn build_group_search_request(
+        &self,
+        collection_name: &str,
+        query: SearchQuery,
+        params: &SearchParams,
+        group_by: &GroupBy,
+    ) -> RecommendPointGroups {
+        let points = self
+            .build_search_builder(collection_name, query, params)
+            .build();
+        RecommendPointGroups {
+            collection_name: points.collection_name,
+            positive: points.positive,
+            negative: points.negative,
+            positive_vectors: points.positive_vectors,
+            negative_vectors: points.negative_vectors,
+            filter: points.filter,
+            limit: params.limit as u32,
+            with_payload: points.with_payload,
+            params: points.params,
+            score_threshold: points.score_threshold,
+            using: points.using,
+            strategy: points.strategy,
+            group_by: group_by.field.clone(),
+            group_size: group_by.group_size,
+            ..Default::default()
+        }
+    }
+
+    fn convert_to_search_group(group: &PointGroup) -> Result<SearchGroup> {
+        let key = match group.id.as_ref().and_then(|id| id.kind.as_ref()) {
+            Some(GroupIdKind::StringValue(value)) => value.clone(),
+            Some(GroupIdKind::IntegerValue(value)) => value.to_string(),
+            Some(GroupIdKind::UnsignedValue(value)) => value.to_string(),
+            None => anyhow::bail!("Group has no id"),
+        };
+        Ok(SearchGroup {
+            key,
+            hits: group
+                .hits
+                .iter()
+                .map(Self::convert_to_search_hit)
+                .collect::<Result<_>>()?,
+        })
+    }
+
+    fn convert_to_search_hit(scored_point: &ScoredPoint) -> Result<SearchHit> {
+        let id = point_id_to_string(scored_point.id.as_ref())?;
+        let payload = serde_json::to_value(&scored_point.payload)
+            .and_then(serde_json::from_value)
+            .with_context(|| format!("Failed to deserialize payload of point {id}"))?;
+        Ok(SearchHit {
+            id,
+            score: scored_point.score,
+            payload,
+        })
+    }
+
+    fn convert_to_stored_point(point: RetrievedPoint) -> Result<StoredPoint> {
+        let id = point_id_to_string(point.id.as_ref())?;
+        let payload = serde_json::to_value(&point.payload)
+            .and_then(serde_json::from_value)
+            .with_context(|| format!("Failed to deserialize payload of point {id}"))?;
+        let vectors = match point.vectors.and_then(|v| v.vectors_options) {
+            Some(VectorsOptions::Vector(vector)) => HashMap::from([(String::new(), vector.into())]),
+            Some(VectorsOptions::Vectors(named)) => named
+                .vectors
+                .into_iter()
+                .map(|(name, vector)| (name, vector.into()))
+                .collect(),
+            None => HashMap::new(),
+        };
+        Ok(StoredPoint {
+            id,
+            vectors,
+            payload,
+        })
+    }
+}
+
+fn parse_collection_version(alias: &str, name: &str) -> Option<u32> {
+    name.strip_prefix(alias)?.strip_prefix("_v")?.parse().ok()
+}
+
+fn point_id_from_str(id: &str) -> PointId {
+    match id.parse::<u64>() {
+        Ok(num) => num.into(),
+        Err(_) => id.into(),
+    }
+}
+
+fn point_id_to_string(id: Option<&PointId>) -> Result<String> {
+    match id.and_then(|id| id.point_id_options.as_ref()) {
+        Some(PointIdOptions::Uuid(uuid)) => Ok(uuid.clone()),
+        Some(PointIdOptions::Num(num)) => Ok(num.to_string()),
+        None => anyhow::bail!("Point has no id"),
+    }
+}
+
+/// Dense tag probabilities.
+pub const TAGS_VECTOR: &str = "tags";
+/// Tag probabilities above a floor, stored as a sparse vector.
+pub const SPARSE_TAGS_VECTOR: &str = "sparse_tags";
+
+/// How tag probabilities are stored in a named vector.
+#[derive(Clone, Copy, Debug)]
+pub enum VectorStorage {
+    /// Dense vector of `size` dimensions, compared with the collection's distance.
+    Dense { size: u64 },
+    /// Only tags with a probability of at least `floor`, compared with dot product.
+    Sparse { floor: f32 },
+}
+
+/// A named vector kept for the points of a collection.
+///
+/// An empty name refers to the unnamed default vector of older collections.
+#[derive(Clone, Debug)]
+pub struct VectorSpec {
+    pub name: String,
+    pub storage: VectorStorage,
+}
+
+impl VectorSpec {
+    pub fn new(name: impl Into<String>, storage: VectorStorage) -> Self {
+        Self {
+            name: name.into(),
+            storage,
+        }
     }
 
-    fn convert_to_point_struct(scored_point: &ScoredPoint) -> Payload {
-        let url = scored_point.payload.get("url").unwrap();
-        let path = scored_point.payload.get("path").unwrap();
-        let hash = scored_point.payload.get("hash").unwrap();
-        Payload {
-            path: path.to_string(),
-            hash: hash.to_string(),
-            url: url.to_string(),
+    pub fn is_sparse(&self) -> bool {
+        matches!(self.storage, VectorStorage::Sparse { .. })
+    }
+
+    pub fn to_vector(&self, probabilities: Vec<f32>) -> Vector {
+        match self.storage {
+            VectorStorage::Dense { .. } => Vector::new_dense(probabilities),
+            VectorStorage::Sparse { floor } => {
+                let (indices, values): (Vec<u32>, Vec<f32>) = probabilities
+                    .into_iter()
+                    .enumerate()
+                    .filter(|&(_, p)| p >= floor)
+                    .map(|(i, p)| (i as u32, p))
+                    .unzip();
+                Vector::new_sparse(indices, values)
+            }
+        }
+    }
+}
+
+/// Encodes one set of tag probabilities into every vector of `specs`.
+pub fn to_named_vectors(specs: &[VectorSpec], probabilities: &[f32]) -> NamedVectors {
+    specs.iter().fold(NamedVectors::default(), |vectors, spec| {
+        vectors.add_vector(&spec.name, spec.to_vector(probabilities.to_vec()))
+    })
+}
+
+/// Distance metric for the dense vectors of a collection.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum VectorDistance {
+    #[default]
+    Cosine,
+    Euclid,
+    Dot,
+    Manhattan,
+}
+
+impl From<VectorDistance> for Distance {
+    fn from(distance: VectorDistance) -> Self {
+        match distance {
+            VectorDistance::Cosine => Distance::Cosine,
+            VectorDistance::Euclid => Distance::Euclid,
+            VectorDistance::Dot => Distance::Dot,
+            VectorDistance::Manhattan => Distance::Manhattan,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Quantization {
+    /// int8 scalar quantisation, about 4x smaller than float32.
+    Scalar,
+    /// One bit per dimension, about 32x smaller than float32.
+    Binary,
+}
+
+/// Index and storage settings used when creating a collection.
+///
+/// Unset values fall back to the Qdrant server defaults.
+#[derive(Clone, Debug, Default)]
+pub struct CollectionOptions {
+    pub distance: VectorDistance,
+    pub hnsw_m: Option<u64>,
+    pub hnsw_ef_construct: Option<u64>,
+    pub quantization: Option<Quantization>,
+    pub on_disk_vectors: Option<bool>,
+    pub on_disk_payload: Option<bool>,
+    pub shard_number: Option<u32>,
+    pub replication_factor: Option<u32>,
+}
+
+/// Payload fields indexed when a collection is created.
+const PAYLOAD_INDEXES: &[(&str, FieldType)] = &[
+    ("hash", FieldType::Keyword),
+    ("path", FieldType::Keyword),
+    ("paths", FieldType::Keyword),
+    ("tags", FieldType::Keyword),
+    ("rating", FieldType::Keyword),
+    ("source_folder", FieldType::Keyword),
+    ("width", FieldType::Integer),
+    ("height", FieldType::Integer),
+    ("indexed_at", FieldType::Integer),
+];
+
+/// Restricts search results by payload; unset fields match everything.
+#[derive(Clone, Debug, Default)]
+pub struct PayloadFilter {
+    /// Every one of these tags must be present.
+    pub tags: Vec<String>,
+    /// The rating must be one of these.
+    pub ratings: Vec<String>,
+    pub source_folder: Option<String>,
+    pub min_width: Option<u32>,
+    pub min_height: Option<u32>,
+    /// Unix timestamp in seconds.
+    pub indexed_after: Option<i64>,
+}
+
+impl PayloadFilter {
+    fn to_filter(&self) -> Option<Filter> {
+        let mut conditions: Vec<Condition> = self
+            .tags
+            .iter()
+            .map(|tag| Condition::matches("tags", tag.clone()))
+            .collect();
+        if !self.ratings.is_empty() {
+            conditions.push(Condition::matches("rating", self.ratings.clone()));
+        }
+        if let Some(source_folder) = &self.source_folder {
+            conditions.push(Condition::matches("source_folder", source_folder.clone()));
+        }
+        if let Some(min_width) = self.min_width {
+            conditions.push(Condition::range("width", at_least(min_width.into())));
+        }
+        if let Some(min_height) = self.min_height {
+            conditions.push(Condition::range("height", at_least(min_height.into())));
+        }
+        if let Some(indexed_after) = self.indexed_after {
+            conditions.push(Condition::range(
+                "indexed_at",
+                at_least(indexed_after as f64),
+            ));
+        }
+        (!conditions.is_empty()).then(|| Filter::must(conditions))
+    }
+}
+
+fn at_least(value: f64) -> Range {
+    Range {
+        gte: Some(value),
+        ..Default::default()
+    }
+}
+
+/// How positive and negative examples are combined in a recommendation search.
+#[derive(Clone, Copy, Debug, Default)]
+pub enum SearchStrategy {
+    /// Search around the average of the positives, pushed away from the negatives.
+    #[default]
+    AverageVector,
+    /// Score each candidate against every example separately; slower but handles
+    /// examples that don't cluster around one point.
+    BestScore,
+}
+
+impl From<SearchStrategy> for RecommendStrategy {
+    fn from(strategy: SearchStrategy) -> Self {
+        match strategy {
+            SearchStrategy::AverageVector => RecommendStrategy::AverageVector,
+            SearchStrategy::BestScore => RecommendStrategy::BestScore,
+        }
+    }
+}
+
+impl FromStr for SearchStrategy {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "average_vector" => Ok(Self::AverageVector),
+            "best_score" => Ok(Self::BestScore),
+            _ => anyhow::bail!("Unknown search strategy: {s}"),
+        }
+    }
+}
+
+/// An example image of a recommendation.
+#[derive(Clone, Debug)]
+pub enum Example {
+    /// Tag probabilities inferred from an image.
+    Vector(Vec<f32>),
+    /// Id of a point already in the collection, used without re-running the model.
+    Point(String),
+}
+
+impl Example {
+    fn into_recommend(self, spec: &VectorSpec) -> RecommendExample {
+        match self {
+            Self::Vector(probabilities) => spec.to_vector(probabilities).into(),
+            Self::Point(id) => point_id_from_str(&id).into(),
+        }
+    }
+}
+
+/// Positive and negative examples of one recommendation.
+#[derive(Clone, Debug, Default)]
+pub struct SearchQuery {
+    pub positive: Vec<Example>,
+    pub negative: Vec<Example>,
+}
+
+/// Refers to an indexed image by point id, blake3 hash, or stored file name or path.
+#[derive(Clone, Debug)]
+pub enum PointRef {
+    Id(String),
+    Hash(String),
+    Path(String),
+}
+
+impl FromStr for PointRef {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.split_once(':') {
+            Some(("id", id)) => Ok(Self::Id(id.to_string())),
+            Some(("hash", hash)) => Ok(Self::Hash(hash.to_string())),
+            Some(("path", path)) => Ok(Self::Path(path.to_string())),
+            _ => {
+                anyhow::bail!("Expected id:<point id>, hash:<blake3> or path:<path>, got {s}")
+            }
         }
     }
 }
 
 pub struct SearchParams {
+    pub vector: VectorSpec,
     pub score_threshold: f32,
     pub exact: bool,
     pub hnsw_ef: u64,
     pub limit: u64,
+    pub filter: PayloadFilter,
+    pub strategy: SearchStrategy,
 }
 
+/// Groups search results by a payload field, e.g. `source_folder`.
+#[derive(Clone, Debug)]
+pub struct GroupBy {
+    /// Keyword or integer payload field to group on.
+    pub field: String,
+    /// Maximum number of hits per group.
+    pub group_size: u32,
+}
+
+/// Payload stored with each image; fields missing on older points are `None`.
+///
+/// Missing fields are left out when serialising so that re-imported points keep
+/// the shape they were exported with.
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct Payload {
-    pub path: String,
-    pub hash: String,
-    pub url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub hash: Option<String>,
+    /// Key of the S3 object holding the image.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<String>,
+    /// Public URL of the object, written by older versions instead of `key`; search results
+    /// carry a presigned URL here.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub url: Option<String>,
+    #[serde(default)]
+    pub tags: Vec<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rating: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub source_folder: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub width: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub height: Option<u32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub indexed_at: Option<i64>,
+    /// Every absolute path a file with this content was indexed from.
+    #[serde(default)]
+    pub paths: Vec<String>,
+    /// Unix timestamp of the first time this content was indexed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub first_seen: Option<i64>,
+    /// Unix timestamp of the latest time this content was indexed.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_seen: Option<i64>,
+}
+
+impl Payload {
+    /// Key of the S3 object holding the image, falling back to the stored URL.
+    ///
+    /// `None` for images that were only indexed in place.
+    pub fn object_key(&self) -> Option<&str> {
+        self.key
+            .as_deref()
+            .or_else(|| self.url.as_deref().map(key_from_url))
+    }
+
+    /// Whether the image was put in object storage, as opposed to only indexed in place.
+    pub fn is_stored(&self) -> bool {
+        self.object_key().is_some()
+    }
+
+    /// Absolute paths the image was indexed from, most recent first.
+    pub fn source_paths(&self) -> Vec<PathBuf> {
+        let mut paths: Vec<PathBuf> = self.paths.iter().rev().map(PathBuf::from).collect();
+        if let (Some(folder), Some(name)) = (&self.source_folder, &self.path) {
+            let path = Path::new(folder).join(name);
+            if !paths.contains(&path) {
+                paths.push(path);
+            }
+        }
+        paths
+    }
+}
+
+/// A point as stored in the collection, used for export and import.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StoredPoint {
+    pub id: String,
+    /// Vectors by name; the unnamed default vector is stored under an empty name.
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub vectors: HashMap<String, VectorData>,
+    pub payload: Payload,
+}
+
+impl StoredPoint {
+    /// Checks that every vector exists in `specs` with the same kind and size.
+    pub fn validate(&self, specs: &[VectorSpec]) -> Result<()> {
+        anyhow::ensure!(!self.vectors.is_empty(), "Point {} has no vectors", self.id);
+        for (name, vector) in &self.vectors {
+            let spec = specs
+                .iter()
+                .find(|spec| spec.name == *name)
+                .with_context(|| format!("Point {} has unknown vector {name:?}", self.id))?;
+            match (vector, spec.storage) {
+                (VectorData::Dense(data), VectorStorage::Dense { size }) => anyhow::ensure!(
+                    data.len() as u64 == size,
+                    "Point {} has {} dimensions in vector {name:?}, expected {size}",
+                    self.id,
+                    data.len()
+                ),
+                (VectorData::Sparse { .. }, VectorStorage::Sparse { .. }) => {}
+                _ => anyhow::bail!("Point {} has a vector {name:?} of the wrong kind", self.id),
+            }
+        }
+        Ok(())
+    }
+
+    /// Infers the vectors of a collection able to hold this point.
+    pub fn vector_specs(&self) -> Vec<VectorSpec> {
+        self.vectors
+            .iter()
+            .map(|(name, vector)| {
+                let storage = match vector {
+                    VectorData::Dense(data) => VectorStorage::Dense {
+                        size: data.len() as u64,
+                    },
+                    VectorData::Sparse { .. } => VectorStorage::Sparse { floor: 0.0 },
+                };
+                VectorSpec::new(name.clone(), storage)
+            })
+            .collect()
+    }
+}
+
+impl TryFrom<StoredPoint> for PointStruct {
+    type Error = anyhow::Error;
+
+    fn try_from(point: StoredPoint) -> Result<Self> {
+        let payload = serde_json::to_value(&point.payload)
+            .map_err(anyhow::Error::from)
+            .and_then(|value| Ok(QdrantPayload::try_from(value)?))
+            .with_context(|| format!("Failed to serialize payload of point {}", point.id))?;
+        let id = point_id_from_str(&point.id);
+        let mut vectors = point.vectors;
+        Ok(match vectors.remove("") {
+            Some(vector) => {
+                anyhow::ensure!(
+                    vectors.is_empty(),
+                    "Point {} mixes the default vector with named vectors",
+                    point.id
+                );
+                PointStruct::new(id, Vector::from(vector), payload)
+            }
+            None => {
+                let named: HashMap<String, Vector> = vectors
+                    .into_iter()
+                    .map(|(name, vector)| (name, vector.into()))
+                    .collect();
+                PointStruct::new(id, NamedVectors::from(named), payload)
+            }
+        })
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+#[serde(untagged)]
+pub enum VectorData {
+    Dense(Vec<f32>),
+    Sparse { indices: Vec<u32>, values: Vec<f32> },
+}
+
+impl From<Vector> for VectorData {
+    fn from(vector: Vector) -> Self {
+        match vector.indices {
+            Some(indices) => Self::Sparse {
+                indices: indices.data,
+                values: vector.data,
+            },
+            None => Self::Dense(vector.data),
+        }
+    }
+}
+
+impl From<VectorData> for Vector {
+    fn from(vector: VectorData) -> Self {
+        match vector {
+            VectorData::Dense(data) => Vector::new_dense(data),
+            VectorData::Sparse { indices, values } => Vector::new_sparse(indices, values),
+        }
+    }
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SnapshotInfo {
+    pub name: String,
+    /// Unix timestamp in seconds.
+    pub created_at: Option<i64>,
+    /// Size in bytes.
+    pub size: i64,
+    /// SHA256 digest of the snapshot file.
+    pub checksum: Option<String>,
+}
+
+impl From<SnapshotDescription> for SnapshotInfo {
+    fn from(description: SnapshotDescription) -> Self {
+        Self {
+            name: description.name,
+            created_at: description.creation_time.map(|time| time.seconds),
+            size: description.size,
+            checksum: description.checksum,
+        }
+    }
+}
+
+pub struct ScrollPage {
+    pub points: Vec<StoredPoint>,
+    /// Offset of the next page, or `None` after the last page.
+    pub next_offset: Option<String>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SearchHit {
+    pub id: String,
+    pub score: f32,
+    pub payload: Payload,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct SearchGroup {
+    /// Value of the grouped field shared by every hit.
+    pub key: String,
+    pub hits: Vec<SearchHit>,
 }
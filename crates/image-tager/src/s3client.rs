@@ -1,35 +1,234 @@
-use anyhow::Result;
+use std::collections::HashMap;
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
 use aws_config::Region;
-use aws_sdk_s3::{config::Credentials, primitives::ByteStream, Client};
+use aws_sdk_s3::{
+    config::Credentials,
+    presigning::PresigningConfig,
+    primitives::ByteStream,
+    types::{CompletedMultipartUpload, CompletedPart, MetadataDirective},
+    Client,
+};
+use aws_smithy_types::byte_stream::Length;
+use futures_util::{Stream, StreamExt, TryStreamExt};
+
+use crate::{content_type, Config};
+
+const DEFAULT_MULTIPART_THRESHOLD: u64 = 64 * 1024 * 1024;
+const PART_SIZE: u64 = 16 * 1024 * 1024;
+const PARALLEL_PARTS: usize = 4;
+const DEFAULT_PRESIGN_EXPIRY: Duration = Duration::from_secs(60 * 60);
+
+/// Attributes stored alongside an uploaded object.
+#[derive(Default, Clone, Debug)]
+pub struct UploadOptions {
+    /// Defaults to the type implied by the key's extension.
+    pub content_type: Option<String>,
+    /// User metadata, sent as `x-amz-meta-*` headers; non-ASCII values are percent-encoded.
+    pub metadata: HashMap<String, String>,
+    /// Object tags, usable in lifecycle rules and access policies.
+    pub tags: Vec<(String, String)>,
+}
+
+impl UploadOptions {
+    fn content_type(&self, key: &str) -> String {
+        self.content_type
+            .clone()
+            .unwrap_or_else(|| content_type(key).to_string())
+    }
 
-use crate::Config;
+    fn metadata(&self) -> Option<HashMap<String, String>> {
+        (!self.metadata.is_empty()).then(|| {
+            self.metadata
+                .iter()
+                .map(|(name, value)| {
+                    (
+                        name.clone(),
+                        percent_encode(value, |b| b.is_ascii_graphic() || b == b' '),
+                    )
+                })
+                .collect()
+        })
+    }
+
+    /// Tags in the URL query form S3 expects in the `x-amz-tagging` header.
+    fn tagging(&self) -> Option<String> {
+        let unreserved = |b: u8| b.is_ascii_alphanumeric() || b"-_.~".contains(&b);
+        (!self.tags.is_empty()).then(|| {
+            self.tags
+                .iter()
+                .map(|(name, value)| {
+                    format!(
+                        "{}={}",
+                        percent_encode(name, unreserved),
+                        percent_encode(value, unreserved)
+                    )
+                })
+                .collect::<Vec<_>>()
+                .join("&")
+        })
+    }
+}
 
 pub struct S3Client {
     client: Client,
     bucket: String,
+    multipart_threshold: u64,
+    presign_expiry: Duration,
 }
 
 impl S3Client {
     pub fn new() -> Result<Self> {
         let app_config = Config::new()?;
-        let client = create_s3_client(&app_config);
+        let client = create_s3_client(&app_config)?;
         Ok(Self {
             client,
-            bucket: app_config.s3_bucket_name.clone(),
+            bucket: required(&app_config.s3_bucket_name, "S3_BUCKET_NAME")?,
+            multipart_threshold: app_config
+                .s3_multipart_threshold
+                .unwrap_or(DEFAULT_MULTIPART_THRESHOLD)
+                .max(PART_SIZE),
+            presign_expiry: app_config
+                .s3_presign_expiry
+                .map_or(DEFAULT_PRESIGN_EXPIRY, Duration::from_secs),
         })
     }
 
-    pub async fn upload_file(&self, key: &str, data: &[u8]) -> Result<()> {
+    pub async fn upload_file(&self, key: &str, data: &[u8], options: &UploadOptions) -> Result<()> {
         self.client
             .put_object()
             .bucket(&self.bucket)
             .key(key)
+            .content_type(options.content_type(key))
+            .set_metadata(options.metadata())
+            .set_tagging(options.tagging())
             .body(ByteStream::from(data.to_vec()))
             .send()
             .await?;
         Ok(())
     }
 
+    /// Uploads the file at `path`, streaming it from disk; large files go up in parts.
+    pub async fn upload_path(&self, key: &str, path: &Path, options: &UploadOptions) -> Result<()> {
+        let size = tokio::fs::metadata(path).await?.len();
+        if size >= self.multipart_threshold {
+            return self.upload_multipart(key, path, size, options).await;
+        }
+        self.client
+            .put_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(options.content_type(key))
+            .set_metadata(options.metadata())
+            .set_tagging(options.tagging())
+            .body(ByteStream::from_path(path).await?)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Uploads `size` bytes from `path` as a multipart upload, several parts at a time,
+    /// aborting the upload if any part fails so no partial object or parts are left behind.
+    async fn upload_multipart(
+        &self,
+        key: &str,
+        path: &Path,
+        size: u64,
+        options: &UploadOptions,
+    ) -> Result<()> {
+        let upload = self
+            .client
+            .create_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .content_type(options.content_type(key))
+            .set_metadata(options.metadata())
+            .set_tagging(options.tagging())
+            .send()
+            .await?;
+        let upload_id = upload.upload_id().context("Multipart upload has no id")?;
+
+        let Err(e) = self.upload_parts(key, upload_id, path, size).await else {
+            return Ok(());
+        };
+        let e = e.context(format!("Failed multipart upload of {}", path.display()));
+        let aborted = self
+            .client
+            .abort_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .send()
+            .await;
+        // Keep the upload failure as the cause; a failed abort only leaves parts behind
+        Err(match aborted {
+            Ok(_) => e,
+            Err(abort) => e.context(format!(
+                "Failed to abort multipart upload {upload_id}: {abort}"
+            )),
+        })
+    }
+
+    async fn upload_parts(&self, key: &str, upload_id: &str, path: &Path, size: u64) -> Result<()> {
+        let mut parts: Vec<CompletedPart> = futures_util::stream::iter(
+            (0..size.div_ceil(PART_SIZE))
+                .map(|index| self.upload_part(key, upload_id, path, index, size)),
+        )
+        .buffer_unordered(PARALLEL_PARTS)
+        .try_collect()
+        .await?;
+        parts.sort_by_key(|part| part.part_number);
+
+        self.client
+            .complete_multipart_upload()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .multipart_upload(
+                CompletedMultipartUpload::builder()
+                    .set_parts(Some(parts))
+                    .build(),
+            )
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    async fn upload_part(
+        &self,
+        key: &str,
+        upload_id: &str,
+        path: &Path,
+        index: u64,
+        size: u64,
+    ) -> Result<CompletedPart> {
+        let offset = index * PART_SIZE;
+        let body = ByteStream::read_from()
+            .path(path)
+            .offset(offset)
+            .length(Length::Exact(PART_SIZE.min(size - offset)))
+            .build()
+            .await?;
+        // Part numbers start at 1
+        let part_number = index as i32 + 1;
+        let output = self
+            .client
+            .upload_part()
+            .bucket(&self.bucket)
+            .key(key)
+            .upload_id(upload_id)
+            .part_number(part_number)
+            .body(body)
+            .send()
+            .await?;
+        Ok(CompletedPart::builder()
+            .set_e_tag(output.e_tag)
+            .part_number(part_number)
+            .build())
+    }
+
     pub async fn download_file(&self, key: &str) -> Result<Vec<u8>> {
         let output = self
             .client
@@ -41,35 +240,163 @@ impl S3Client {
         Ok(output.body.collect().await?.to_vec())
     }
 
-    pub async fn search_file(&self, key: &str) -> Result<bool> {
-        let output = self
+    /// A URL granting GET access to the object at `key` for the configured expiry, so
+    /// private buckets can be read over plain HTTP.
+    pub async fn presigned_url(&self, key: &str) -> Result<String> {
+        self.presigned_url_with_expiry(key, self.presign_expiry)
+            .await
+    }
+
+    pub async fn presigned_url_with_expiry(
+        &self,
+        key: &str,
+        expires_in: Duration,
+    ) -> Result<String> {
+        let request = self
             .client
             .get_object()
             .bucket(&self.bucket)
             .key(key)
+            .presigned(PresigningConfig::expires_in(expires_in)?)
+            .await?;
+        Ok(request.uri().to_string())
+    }
+
+    /// Copies the object at `from` to `to` within the bucket, keeping its metadata and tags
+    /// but taking the content type from the new key.
+    pub async fn copy_file(&self, from: &str, to: &str) -> Result<()> {
+        let source = self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(from)
             .send()
-            .await;
-        Ok(output.is_ok())
+            .await?;
+        self.client
+            .copy_object()
+            .bucket(&self.bucket)
+            .copy_source(format!("{}/{from}", self.bucket))
+            .key(to)
+            .content_type(content_type(to))
+            .set_metadata(source.metadata)
+            .metadata_directive(MetadataDirective::Replace)
+            .send()
+            .await?;
+        Ok(())
     }
 
-    pub async fn list_files(&self, prefix: Option<&str>) -> Result<Vec<String>> {
-        let mut request = self.client.list_objects_v2().bucket(&self.bucket);
-        if let Some(prefix) = prefix {
-            request = request.prefix(prefix);
+    pub async fn delete_file(&self, key: &str) -> Result<()> {
+        self.client
+            .delete_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await?;
+        Ok(())
+    }
+
+    /// Whether an object exists at `key`, using a HEAD request. Only a not-found response
+    /// counts as missing; other failures such as denied access are returned as errors.
+    pub async fn file_exists(&self, key: &str) -> Result<bool> {
+        match self
+            .client
+            .head_object()
+            .bucket(&self.bucket)
+            .key(key)
+            .send()
+            .await
+        {
+            Ok(_) => Ok(true),
+            Err(e)
+                if e.as_service_error().is_some_and(|e| e.is_not_found())
+                    || e.raw_response().map(|r| r.status().as_u16()) == Some(404) =>
+            {
+                Ok(false)
+            }
+            Err(e) => Err(e).with_context(|| format!("Failed to check object {key}")),
         }
-        let output = request.send().await?;
-        Ok(output
-            .contents()
-            .iter()
-            .filter_map(|obj| obj.key().map(String::from))
-            .collect())
     }
+
+    /// Keys of every object under `prefix`, following continuation tokens past the first page.
+    pub async fn list_files(&self, prefix: Option<&str>) -> Result<Vec<String>> {
+        self.list_files_stream(prefix).try_collect().await
+    }
+
+    /// Keys of every object under `prefix`, fetching the next page only once the stream has
+    /// consumed the previous one.
+    pub fn list_files_stream<'a>(
+        &'a self,
+        prefix: Option<&'a str>,
+    ) -> impl Stream<Item = Result<String>> + 'a {
+        // `None` once the last page has been fetched, otherwise the token to continue from
+        futures_util::stream::try_unfold(Some(None), move |token| async move {
+            let Some(token) = token else {
+                return anyhow::Ok(None);
+            };
+            let page = self.list_page(prefix, token).await?;
+            anyhow::Ok(Some((page.keys, page.next_token.map(Some))))
+        })
+        .map_ok(|keys| futures_util::stream::iter(keys.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
+    /// One page of keys under `prefix`, starting at `continuation_token` or at the beginning.
+    pub async fn list_page(
+        &self,
+        prefix: Option<&str>,
+        continuation_token: Option<String>,
+    ) -> Result<ListPage> {
+        let output = self
+            .client
+            .list_objects_v2()
+            .bucket(&self.bucket)
+            .set_prefix(prefix.map(String::from))
+            .set_continuation_token(continuation_token)
+            .send()
+            .await?;
+        Ok(ListPage {
+            keys: output
+                .contents()
+                .iter()
+                .filter_map(|obj| obj.key().map(String::from))
+                .collect(),
+            next_token: output
+                .next_continuation_token()
+                .filter(|_| output.is_truncated().unwrap_or(false))
+                .map(String::from),
+        })
+    }
+}
+
+pub struct ListPage {
+    pub keys: Vec<String>,
+    pub next_token: Option<String>,
+}
+
+/// Percent-encodes every byte of `value` that `keep` rejects.
+fn percent_encode(value: &str, keep: impl Fn(u8) -> bool) -> String {
+    value
+        .bytes()
+        .map(|b| {
+            if keep(b) && b != b'%' {
+                char::from(b).to_string()
+            } else {
+                format!("%{b:02X}")
+            }
+        })
+        .collect()
+}
+
+fn required(value: &Option<String>, name: &str) -> Result<String> {
+    value
+        .clone()
+        .with_context(|| format!("{name} is required for the S3 storage backend"))
 }
 
-fn create_s3_client(config: &Config) -> Client {
+fn create_s3_client(config: &Config) -> Result<Client> {
     let credentials = Credentials::new(
-        &config.aws_access_key_id,
-        &config.aws_secret_access_key,
+        required(&config.aws_access_key_id, "AWS_ACCESS_KEY_ID")?,
+        required(&config.aws_secret_access_key, "AWS_SECRET_ACCESS_KEY")?,
         None,
         None,
         "example",
@@ -77,9 +404,9 @@ fn create_s3_client(config: &Config) -> Client {
     let config = aws_sdk_s3::Config::builder()
         .behavior_version_latest()
         .credentials_provider(credentials)
-        .region(Region::new(config.aws_region.clone()))
+        .region(Region::new(required(&config.aws_region, "AWS_REGION")?))
         .force_path_style(true)
-        .endpoint_url(&config.s3_endpoint)
+        .endpoint_url(required(&config.s3_endpoint, "S3_ENDPOINT")?)
         .build();
-    Client::from_conf(config)
+    Ok(Client::from_conf(config))
 }
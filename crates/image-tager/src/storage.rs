@@ -0,0 +1,381 @@
+use std::{
+    path::{Component, Path, PathBuf},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use walkdir::WalkDir;
+
+use crate::{Config, S3Client, UploadOptions};
+
+/// Where image objects are kept.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum StorageBackend {
+    S3,
+    /// A local directory, for small deployments and tests without an S3 server.
+    Local,
+    /// No object storage: points reference the indexed files where they are.
+    None,
+}
+
+/// Content-addressed object storage, keyed by the keys of [`crate::StorageLayout`].
+#[allow(async_fn_in_trait)]
+pub trait ObjectStorage {
+    async fn put(&self, key: &str, data: &[u8], options: &UploadOptions) -> Result<()>;
+    /// Stores the file at `path` without reading it into memory first.
+    async fn put_path(&self, key: &str, path: &Path, options: &UploadOptions) -> Result<()>;
+    async fn get(&self, key: &str) -> Result<Vec<u8>>;
+    async fn exists(&self, key: &str) -> Result<bool>;
+    /// Keys of every object under `prefix`.
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<String>>;
+    /// Removes the object at `key`; removing a missing object is not an error.
+    async fn delete(&self, key: &str) -> Result<()>;
+    async fn copy(&self, from: &str, to: &str) -> Result<()>;
+    /// A URL the object can be fetched from without credentials.
+    async fn url(&self, key: &str) -> Result<String>;
+}
+
+impl ObjectStorage for S3Client {
+    async fn put(&self, key: &str, data: &[u8], options: &UploadOptions) -> Result<()> {
+        self.upload_file(key, data, options).await
+    }
+
+    async fn put_path(&self, key: &str, path: &Path, options: &UploadOptions) -> Result<()> {
+        self.upload_path(key, path, options).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        self.download_file(key).await
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        self.file_exists(key).await
+    }
+
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<String>> {
+        self.list_files(prefix).await
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.delete_file(key).await
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        self.copy_file(from, to).await
+    }
+
+    async fn url(&self, key: &str) -> Result<String> {
+        self.presigned_url(key).await
+    }
+}
+
+/// Objects stored as files under a root directory, at their key as relative path.
+/// Upload metadata and tags are not kept.
+pub struct LocalStorage {
+    root: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new(root: impl Into<PathBuf>) -> Result<Self> {
+        let root = root.into();
+        std::fs::create_dir_all(&root)
+            .with_context(|| format!("Failed to create storage directory {}", root.display()))?;
+        Ok(Self {
+            root: dunce::canonicalize(&root)?,
+        })
+    }
+
+    /// Path of the object at `key`, refusing keys that would leave the root.
+    fn path(&self, key: &str) -> Result<PathBuf> {
+        let relative = Path::new(key);
+        anyhow::ensure!(
+            relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_))),
+            "Invalid object key: {key}"
+        );
+        Ok(self.root.join(relative))
+    }
+
+    /// Path of the object at `key`, and a temporary path unique to this write to fill first,
+    /// so readers never see a partial object and concurrent writers of a key don't collide.
+    async fn staging(&self, key: &str) -> Result<(PathBuf, PathBuf)> {
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let path = self.path(key)?;
+        if let Some(parent) = path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        let mut name = path.file_name().unwrap_or_default().to_os_string();
+        name.push(format!(
+            ".{}.{}.partial",
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        let partial = path.with_file_name(name);
+        Ok((path, partial))
+    }
+
+    /// Copies `source` to the object at `key`.
+    async fn copy_in(&self, source: &Path, key: &str) -> Result<()> {
+        let (path, partial) = self.staging(key).await?;
+        let written = tokio::fs::copy(source, &partial)
+            .await
+            .map(drop)
+            .with_context(|| format!("Failed to copy {}", source.display()));
+        commit(written, &partial, &path).await
+    }
+}
+
+/// Moves a filled temporary file into place, or removes it if filling it failed.
+async fn commit(written: Result<()>, partial: &Path, path: &Path) -> Result<()> {
+    if let Err(e) = written {
+        let _ = tokio::fs::remove_file(partial).await;
+        return Err(e);
+    }
+    tokio::fs::rename(partial, path).await?;
+    Ok(())
+}
+
+impl ObjectStorage for LocalStorage {
+    async fn put(&self, key: &str, data: &[u8], _options: &UploadOptions) -> Result<()> {
+        let (path, partial) = self.staging(key).await?;
+        let written = tokio::fs::write(&partial, data)
+            .await
+            .with_context(|| format!("Failed to write {}", partial.display()));
+        commit(written, &partial, &path).await
+    }
+
+    async fn put_path(&self, key: &str, path: &Path, _options: &UploadOptions) -> Result<()> {
+        self.copy_in(path, key).await
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let path = self.path(key)?;
+        tokio::fs::read(&path)
+            .await
+            .with_context(|| format!("Failed to read {}", path.display()))
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        Ok(tokio::fs::try_exists(self.path(key)?).await?)
+    }
+
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<String>> {
+        let root = self.root.clone();
+        let prefix = prefix.unwrap_or_default().to_string();
+        tokio::task::spawn_blocking(move || {
+            let mut keys = Vec::new();
+            for entry in WalkDir::new(&root) {
+                let entry = entry?;
+                if !entry.file_type().is_file()
+                    || entry.path().extension().is_some_and(|ext| ext == "partial")
+                {
+                    continue;
+                }
+                let relative = entry.path().strip_prefix(&root)?;
+                let key = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect::<Vec<_>>()
+                    .join("/");
+                if key.starts_with(&prefix) {
+                    keys.push(key);
+                }
+            }
+            Ok(keys)
+        })
+        .await?
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        match tokio::fs::remove_file(self.path(key)?).await {
+            Err(e) if e.kind() != std::io::ErrorKind::NotFound => Err(e.into()),
+            _ => Ok(()),
+        }
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        self.copy_in(&self.path(from)?, to).await
+    }
+
+    async fn url(&self, key: &str) -> Result<String> {
+        let path = self.path(key)?;
+        reqwest::Url::from_file_path(&path)
+            .map(String::from)
+            .map_err(|()| anyhow::anyhow!("Not an absolute path: {}", path.display()))
+    }
+}
+
+/// The storage backend selected by `STORAGE_BACKEND`.
+pub enum Storage {
+    S3(S3Client),
+    Local(LocalStorage),
+}
+
+impl Storage {
+    /// The configured backend, failing in index-only mode.
+    pub fn new() -> Result<Self> {
+        Self::from_config()?.context("No object storage is configured")
+    }
+
+    /// The configured backend, or `None` in index-only mode.
+    pub fn from_config() -> Result<Option<Self>> {
+        let app_config = Config::new()?;
+        Ok(match app_config.storage_backend() {
+            StorageBackend::S3 => Some(Self::S3(S3Client::new()?)),
+            StorageBackend::Local => Some(Self::Local(LocalStorage::new(
+                app_config
+                    .local_storage_dir
+                    .context("LOCAL_STORAGE_DIR is required for the local storage backend")?,
+            )?)),
+            StorageBackend::None => None,
+        })
+    }
+}
+
+/// Forwards a call to whichever backend is selected.
+macro_rules! dispatch {
+    ($self:ident.$method:ident($($arg:expr),*)) => {
+        match $self {
+            Storage::S3(storage) => storage.$method($($arg),*).await,
+            Storage::Local(storage) => storage.$method($($arg),*).await,
+        }
+    };
+}
+
+impl ObjectStorage for Storage {
+    async fn put(&self, key: &str, data: &[u8], options: &UploadOptions) -> Result<()> {
+        dispatch!(self.put(key, data, options))
+    }
+
+    async fn put_path(&self, key: &str, path: &Path, options: &UploadOptions) -> Result<()> {
+        dispatch!(self.put_path(key, path, options))
+    }
+
+    async fn get(&self, key: &str) -> Result<Vec<u8>> {
+        dispatch!(self.get(key))
+    }
+
+    async fn exists(&self, key: &str) -> Result<bool> {
+        dispatch!(self.exists(key))
+    }
+
+    async fn list(&self, prefix: Option<&str>) -> Result<Vec<String>> {
+        dispatch!(self.list(prefix))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        dispatch!(self.delete(key))
+    }
+
+    async fn copy(&self, from: &str, to: &str) -> Result<()> {
+        dispatch!(self.copy(from, to))
+    }
+
+    async fn url(&self, key: &str) -> Result<String> {
+        dispatch!(self.url(key))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: &str = "ab/cd/abcdef.png";
+
+    fn storage() -> (tempfile::TempDir, LocalStorage) {
+        let dir = tempfile::tempdir().unwrap();
+        let storage = LocalStorage::new(dir.path().join("objects")).unwrap();
+        (dir, storage)
+    }
+
+    #[tokio::test]
+    async fn put_and_get() {
+        let (_dir, storage) = storage();
+        assert!(!storage.exists(KEY).await.unwrap());
+        storage
+            .put(KEY, b"image", &UploadOptions::default())
+            .await
+            .unwrap();
+        assert!(storage.exists(KEY).await.unwrap());
+        assert_eq!(storage.get(KEY).await.unwrap(), b"image");
+    }
+
+    #[tokio::test]
+    async fn put_path_copies_the_file() {
+        let (dir, storage) = storage();
+        let source = dir.path().join("source.png");
+        std::fs::write(&source, b"from disk").unwrap();
+        storage
+            .put_path("abcdef.png", &source, &UploadOptions::default())
+            .await
+            .unwrap();
+        assert_eq!(storage.get("abcdef.png").await.unwrap(), b"from disk");
+        assert!(source.exists());
+    }
+
+    #[tokio::test]
+    async fn concurrent_puts_of_a_key_succeed() {
+        let (_dir, storage) = storage();
+        let options = UploadOptions::default();
+        let (first, second) = tokio::join!(
+            storage.put(KEY, b"image", &options),
+            storage.put(KEY, b"image", &options)
+        );
+        first.unwrap();
+        second.unwrap();
+        assert_eq!(storage.list(None).await.unwrap(), [KEY]);
+    }
+
+    #[tokio::test]
+    async fn list_filters_by_prefix_and_skips_partial_files() {
+        let (_dir, storage) = storage();
+        let options = UploadOptions::default();
+        for key in [KEY, "ab/ef/abef01.jpg", "flat.png"] {
+            storage.put(key, b"image", &options).await.unwrap();
+        }
+        std::fs::write(storage.root.join("ab/cd/abcdef.png.1.2.partial"), b"half").unwrap();
+
+        let mut keys = storage.list(None).await.unwrap();
+        keys.sort_unstable();
+        assert_eq!(keys, ["ab/cd/abcdef.png", "ab/ef/abef01.jpg", "flat.png"]);
+        assert_eq!(storage.list(Some("ab/cd/")).await.unwrap(), [KEY]);
+    }
+
+    #[tokio::test]
+    async fn delete_ignores_missing_keys() {
+        let (_dir, storage) = storage();
+        storage
+            .put(KEY, b"image", &UploadOptions::default())
+            .await
+            .unwrap();
+        storage.delete(KEY).await.unwrap();
+        assert!(!storage.exists(KEY).await.unwrap());
+        storage.delete(KEY).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn copy_keeps_the_source() {
+        let (_dir, storage) = storage();
+        storage
+            .put("abcdef.png", b"image", &UploadOptions::default())
+            .await
+            .unwrap();
+        storage.copy("abcdef.png", KEY).await.unwrap();
+        assert_eq!(storage.get(KEY).await.unwrap(), b"image");
+        assert!(storage.exists("abcdef.png").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn rejects_keys_outside_the_root() {
+        let (dir, storage) = storage();
+        let options = UploadOptions::default();
+        for key in ["../escape.png", "ab/../../escape.png", "/tmp/escape.png"] {
+            assert!(storage.put(key, b"image", &options).await.is_err());
+            assert!(storage.get(key).await.is_err());
+        }
+        assert!(!dir.path().join("escape.png").exists());
+    }
+}
@@ -0,0 +1,163 @@
+use std::path::Path;
+
+use anyhow::{Context, Result};
+use image::ImageFormat;
+
+use crate::Config;
+
+/// How images are laid out in the bucket.
+///
+/// Objects are content addressed: the key is the blake3 hash of the file followed by the
+/// canonical extension of its format, optionally sharded by the first two byte pairs of the
+/// hash (`ab/cd/abcd….jpg`) to keep prefixes small in large buckets.
+#[derive(Clone, Copy, Debug)]
+pub struct StorageLayout {
+    pub sharded: bool,
+}
+
+impl StorageLayout {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            sharded: config.s3_key_sharding.unwrap_or(false),
+        }
+    }
+
+    /// Key of the object holding an image with `hash` in `format`.
+    pub fn key(&self, hash: &str, format: ImageFormat) -> String {
+        let name = format!("{hash}.{}", format.extensions_str()[0]);
+        match (self.sharded, hash.get(..2), hash.get(2..4)) {
+            (true, Some(first), Some(second)) => format!("{first}/{second}/{name}"),
+            _ => name,
+        }
+    }
+
+    /// Key of the object holding the image file at `path`, whose content hashes to `hash`.
+    pub fn key_for_path(&self, hash: &str, path: &Path) -> Result<String> {
+        let format = ImageFormat::from_path(path)
+            .with_context(|| format!("Unknown image format: {}", path.display()))?;
+        Ok(self.key(hash, format))
+    }
+
+    /// The key `key` would have under this layout.
+    pub fn normalize_key(&self, key: &str) -> Result<String> {
+        self.key_for_path(key_hash(key), Path::new(key))
+    }
+}
+
+/// Hash part of an object key such as `ab/cd/<hash>.jpg`.
+pub fn key_hash(key: &str) -> &str {
+    let name = key.rsplit('/').next().unwrap_or(key);
+    name.split('.').next().unwrap_or(name)
+}
+
+/// Object key at the end of `url`, including shard directories that match its hash.
+pub fn key_from_url(url: &str) -> &str {
+    let name_start = url.rfind('/').map_or(0, |i| i + 1);
+    let hash = key_hash(&url[name_start..]);
+    let (Some(first), Some(second)) = (hash.get(..2), hash.get(2..4)) else {
+        return &url[name_start..];
+    };
+    let shard = format!("{first}/{second}/");
+    match url[..name_start].strip_suffix(&shard) {
+        Some(rest) if rest.is_empty() || rest.ends_with('/') => &url[rest.len()..],
+        _ => &url[name_start..],
+    }
+}
+
+/// Content type to store the object at `key` with, from its extension.
+pub fn content_type(key: &str) -> &'static str {
+    ImageFormat::from_path(key).map_or("application/octet-stream", |format| format.to_mime_type())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "abcdef0123";
+    const SHARDED: StorageLayout = StorageLayout { sharded: true };
+    const FLAT: StorageLayout = StorageLayout { sharded: false };
+
+    #[test]
+    fn key_uses_canonical_extension_and_shards() {
+        assert_eq!(FLAT.key(HASH, ImageFormat::Jpeg), "abcdef0123.jpg");
+        assert_eq!(SHARDED.key(HASH, ImageFormat::Png), "ab/cd/abcdef0123.png");
+        // Too short to shard
+        assert_eq!(SHARDED.key("abc", ImageFormat::Png), "abc.png");
+    }
+
+    #[test]
+    fn normalize_key_moves_between_layouts() {
+        assert_eq!(
+            SHARDED.normalize_key("abcdef0123.jpeg").unwrap(),
+            "ab/cd/abcdef0123.jpg"
+        );
+        assert_eq!(
+            FLAT.normalize_key("ab/cd/abcdef0123.jpg").unwrap(),
+            "abcdef0123.jpg"
+        );
+        assert_eq!(
+            SHARDED.normalize_key("ab/cd/abcdef0123.jpg").unwrap(),
+            "ab/cd/abcdef0123.jpg"
+        );
+    }
+
+    #[test]
+    fn normalize_key_rejects_non_images() {
+        assert!(FLAT.normalize_key("abcdef0123").is_err());
+        assert!(SHARDED.normalize_key("ab/cd/abcdef0123.txt").is_err());
+    }
+
+    #[test]
+    fn key_hash_strips_shards_and_extension() {
+        assert_eq!(key_hash("ab/cd/abcdef0123.jpg"), HASH);
+        assert_eq!(key_hash("abcdef0123.tar.gz"), HASH);
+        assert_eq!(key_hash(HASH), HASH);
+    }
+
+    #[test]
+    fn key_from_url_keeps_matching_shards() {
+        assert_eq!(
+            key_from_url("https://s3.example.com/bucket/ab/cd/abcdef0123.jpg"),
+            "ab/cd/abcdef0123.jpg"
+        );
+        assert_eq!(
+            key_from_url("https://s3.example.com/bucket/abcdef0123.jpg"),
+            "abcdef0123.jpg"
+        );
+    }
+
+    #[test]
+    fn key_from_url_ignores_directories_that_are_not_shards() {
+        assert_eq!(
+            key_from_url("https://s3.example.com/bucket/ab/cx/abcdef0123.jpg"),
+            "abcdef0123.jpg"
+        );
+        // The shard must be whole path segments
+        assert_eq!(
+            key_from_url("https://s3.example.com/xab/cd/abcdef0123.jpg"),
+            "abcdef0123.jpg"
+        );
+    }
+
+    #[test]
+    fn key_from_url_at_bucket_root() {
+        assert_eq!(
+            key_from_url("https://bucket.s3.example.com/ab/cd/abcdef0123.jpg"),
+            "ab/cd/abcdef0123.jpg"
+        );
+        assert_eq!(key_from_url("ab/cd/abcdef0123.jpg"), "ab/cd/abcdef0123.jpg");
+        assert_eq!(key_from_url("abcdef0123.jpg"), "abcdef0123.jpg");
+    }
+
+    #[test]
+    fn key_from_url_without_image_extension() {
+        assert_eq!(
+            key_from_url("https://s3.example.com/bucket/ab/cd/abcdef0123"),
+            "ab/cd/abcdef0123"
+        );
+        assert_eq!(
+            key_from_url("https://s3.example.com/bucket/notes.txt"),
+            "notes.txt"
+        );
+    }
+}
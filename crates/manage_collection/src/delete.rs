@@ -0,0 +1,109 @@
+use anyhow::Result;
+use clap::Args;
+
+use image_tager::{
+    Config as AppConfig, DeletePlan, DeleteTarget, PayloadFilter, PointRef, QdrantWrapper, Storage,
+};
+
+#[derive(Args)]
+pub struct DeleteArgs {
+    /// Images to delete: id:<point id>, hash:<blake3> or path:<file name or source path>
+    targets: Vec<PointRef>,
+    /// Delete images with this tag (repeatable; all must match)
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// Delete images with this rating (repeatable; any may match)
+    #[arg(long = "rating")]
+    ratings: Vec<String>,
+    #[arg(long)]
+    source_folder: Option<String>,
+    /// Delete images indexed at or after this Unix timestamp
+    #[arg(long)]
+    indexed_after: Option<i64>,
+    /// List what would be deleted without deleting anything
+    #[arg(long)]
+    dry_run: bool,
+    /// Print the deletion plan as JSON
+    #[arg(long)]
+    json: bool,
+    /// Collection to delete from instead of the configured one
+    #[arg(short, long)]
+    collection: Option<String>,
+}
+
+impl DeleteArgs {
+    fn target(&self) -> Result<DeleteTarget> {
+        let filter = PayloadFilter {
+            tags: self.tags.clone(),
+            ratings: self.ratings.clone(),
+            source_folder: self.source_folder.clone(),
+            indexed_after: self.indexed_after,
+            ..Default::default()
+        };
+        let has_filter = !filter.tags.is_empty()
+            || !filter.ratings.is_empty()
+            || filter.source_folder.is_some()
+            || filter.indexed_after.is_some();
+
+        match (self.targets.is_empty(), has_filter) {
+            (false, false) => Ok(DeleteTarget::Refs(self.targets.clone())),
+            (true, true) => Ok(DeleteTarget::Filter(filter)),
+            (false, true) => anyhow::bail!("Give either image references or filters, not both"),
+            (true, false) => anyhow::bail!("Nothing selected; give image references or filters"),
+        }
+    }
+}
+
+pub struct Deleter {
+    qdrant_client: QdrantWrapper,
+    /// `None` in index-only mode, where only points are deleted.
+    storage: Option<Storage>,
+    app_config: AppConfig,
+}
+
+impl Deleter {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            qdrant_client: QdrantWrapper::new()?,
+            storage: Storage::from_config()?,
+            app_config: AppConfig::new()?,
+        })
+    }
+
+    pub async fn process(&self, args: &DeleteArgs) -> Result<()> {
+        let collection = args
+            .collection
+            .as_deref()
+            .unwrap_or(&self.app_config.collection_name);
+        let mut plan = DeletePlan::new(&self.qdrant_client, collection, &args.target()?).await?;
+        if self.storage.is_none() {
+            plan.objects.clear();
+            plan.kept_objects.clear();
+        }
+
+        if args.json {
+            println!("{}", serde_json::to_string_pretty(&plan)?);
+        } else {
+            print_plan(&plan, args.dry_run);
+        }
+        if !args.dry_run {
+            plan.execute(&self.qdrant_client, self.storage.as_ref())
+                .await?;
+        }
+        Ok(())
+    }
+}
+
+fn print_plan(plan: &DeletePlan, dry_run: bool) {
+    let verb = if dry_run { "Would delete" } else { "Deleting" };
+    for point in &plan.points {
+        let name = point.payload.path.as_deref().unwrap_or("-");
+        println!("{verb} point {} ({name})", point.id);
+    }
+    for key in &plan.objects {
+        println!("{verb} object {key}");
+    }
+    for key in &plan.kept_objects {
+        println!("Keeping object {key}, still referenced by other points");
+    }
+}
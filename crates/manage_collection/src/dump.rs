@@ -0,0 +1,453 @@
+use std::{
+    any::type_name,
+    fs::File,
+    io::{BufRead, BufReader, BufWriter, Lines, Write},
+    path::Path,
+    sync::Arc,
+};
+
+use anyhow::{Context, Result};
+use arrow_array::{
+    builder::{ListBuilder, StringBuilder},
+    cast::AsArray,
+    types::{Int64Type, UInt32Type},
+    Array, ArrayRef, ArrowPrimitiveType, Int64Array, ListArray, PrimitiveArray, RecordBatch,
+    StringArray, UInt32Array,
+};
+use arrow_schema::{DataType, Field, Schema, SchemaRef};
+use clap::ValueEnum;
+use parquet::arrow::{
+    arrow_reader::{ParquetRecordBatchReader, ParquetRecordBatchReaderBuilder},
+    ArrowWriter,
+};
+
+use image_tager::{Payload, StoredPoint};
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum DumpFormat {
+    /// One JSON object per point and line
+    Jsonl,
+    /// One column per payload field, plus `id` and `vectors` as a JSON string
+    Parquet,
+}
+
+impl DumpFormat {
+    /// Picks the format from an explicit choice or the file extension, defaulting to JSONL.
+    pub fn resolve(format: Option<Self>, path: &Path) -> Self {
+        format.unwrap_or_else(|| match path.extension().and_then(|e| e.to_str()) {
+            Some("parquet") => Self::Parquet,
+            _ => Self::Jsonl,
+        })
+    }
+}
+
+pub enum DumpWriter {
+    Jsonl(BufWriter<File>),
+    Parquet(Box<ArrowWriter<File>>),
+}
+
+impl DumpWriter {
+    pub fn create(path: &Path, format: DumpFormat) -> Result<Self> {
+        let file = File::create(path).context("Failed to create dump file")?;
+        Ok(match format {
+            DumpFormat::Jsonl => Self::Jsonl(BufWriter::new(file)),
+            DumpFormat::Parquet => {
+                Self::Parquet(Box::new(ArrowWriter::try_new(file, schema(), None)?))
+            }
+        })
+    }
+
+    pub fn write(&mut self, points: &[StoredPoint]) -> Result<()> {
+        match self {
+            Self::Jsonl(writer) => {
+                for point in points {
+                    serde_json::to_writer(&mut *writer, point)?;
+                    writer.write_all(b"\n")?;
+                }
+            }
+            Self::Parquet(writer) => writer.write(&to_record_batch(points)?)?,
+        }
+        Ok(())
+    }
+
+    pub fn finish(self) -> Result<()> {
+        match self {
+            Self::Jsonl(mut writer) => writer.flush()?,
+            Self::Parquet(writer) => {
+                writer.close()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+pub struct DumpReader {
+    source: DumpSource,
+    batch_size: usize,
+    len: u64,
+}
+
+enum DumpSource {
+    Jsonl(Lines<BufReader<File>>),
+    Parquet(ParquetRecordBatchReader),
+}
+
+impl DumpReader {
+    pub fn open(path: &Path, format: DumpFormat, batch_size: usize) -> Result<Self> {
+        let open = || File::open(path).context("Failed to open dump file");
+        let (source, len) = match format {
+            DumpFormat::Jsonl => {
+                let len = BufReader::new(open()?)
+                    .lines()
+                    .filter(|line| line.as_ref().map_or(true, |l| !l.trim().is_empty()))
+                    .count();
+                (
+                    DumpSource::Jsonl(BufReader::new(open()?).lines()),
+                    len as u64,
+                )
+            }
+            DumpFormat::Parquet => {
+                let builder = ParquetRecordBatchReaderBuilder::try_new(open()?)?;
+                let len = builder.metadata().file_metadata().num_rows() as u64;
+                let reader = builder.with_batch_size(batch_size).build()?;
+                (DumpSource::Parquet(reader), len)
+            }
+        };
+        Ok(Self {
+            source,
+            batch_size,
+            len,
+        })
+    }
+
+    /// Number of points in the dump.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Reads up to `batch_size` points, or `None` once the dump is exhausted.
+    pub fn next_batch(&mut self) -> Result<Option<Vec<StoredPoint>>> {
+        match &mut self.source {
+            DumpSource::Jsonl(lines) => {
+                let mut points = Vec::with_capacity(self.batch_size);
+                for line in lines.by_ref() {
+                    let line = line?;
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    points.push(serde_json::from_str(&line).context("Invalid dump line")?);
+                    if points.len() == self.batch_size {
+                        break;
+                    }
+                }
+                Ok((!points.is_empty()).then_some(points))
+            }
+            DumpSource::Parquet(reader) => reader
+                .next()
+                .map(|batch| from_record_batch(&batch?))
+                .transpose(),
+        }
+    }
+}
+
+fn schema() -> SchemaRef {
+    let string_list =
+        |name| Field::new_list(name, Field::new_list_field(DataType::Utf8, true), false);
+    Arc::new(Schema::new(vec![
+        Field::new("id", DataType::Utf8, false),
+        Field::new("path", DataType::Utf8, true),
+        Field::new("hash", DataType::Utf8, true),
+        Field::new("key", DataType::Utf8, true),
+        Field::new("url", DataType::Utf8, true),
+        string_list("tags"),
+        Field::new("rating", DataType::Utf8, true),
+        Field::new("source_folder", DataType::Utf8, true),
+        Field::new("width", DataType::UInt32, true),
+        Field::new("height", DataType::UInt32, true),
+        Field::new("indexed_at", DataType::Int64, true),
+        string_list("paths"),
+        Field::new("first_seen", DataType::Int64, true),
+        Field::new("last_seen", DataType::Int64, true),
+        Field::new("vectors", DataType::Utf8, true),
+    ]))
+}
+
+fn to_record_batch(points: &[StoredPoint]) -> Result<RecordBatch> {
+    let ids = StringArray::from_iter_values(points.iter().map(|p| p.id.as_str()));
+    let vectors = points
+        .iter()
+        .map(|p| {
+            (!p.vectors.is_empty())
+                .then(|| serde_json::to_string(&p.vectors))
+                .transpose()
+        })
+        .collect::<serde_json::Result<Vec<_>>>()?;
+
+    Ok(RecordBatch::try_new(
+        schema(),
+        vec![
+            Arc::new(ids),
+            strings(points, |p| p.path.as_deref()),
+            strings(points, |p| p.hash.as_deref()),
+            strings(points, |p| p.key.as_deref()),
+            strings(points, |p| p.url.as_deref()),
+            string_lists(points, |p| &p.tags),
+            strings(points, |p| p.rating.as_deref()),
+            strings(points, |p| p.source_folder.as_deref()),
+            primitives::<UInt32Type>(points, |p| p.width),
+            primitives::<UInt32Type>(points, |p| p.height),
+            primitives::<Int64Type>(points, |p| p.indexed_at),
+            string_lists(points, |p| &p.paths),
+            primitives::<Int64Type>(points, |p| p.first_seen),
+            primitives::<Int64Type>(points, |p| p.last_seen),
+            Arc::new(StringArray::from(vectors)),
+        ],
+    )?)
+}
+
+fn strings(points: &[StoredPoint], field: impl Fn(&Payload) -> Option<&str>) -> ArrayRef {
+    Arc::new(StringArray::from_iter(
+        points.iter().map(|p| field(&p.payload)),
+    ))
+}
+
+fn primitives<T: ArrowPrimitiveType>(
+    points: &[StoredPoint],
+    field: impl Fn(&Payload) -> Option<T::Native>,
+) -> ArrayRef {
+    Arc::new(PrimitiveArray::<T>::from_iter(
+        points.iter().map(|p| field(&p.payload)),
+    ))
+}
+
+fn string_lists(points: &[StoredPoint], field: impl Fn(&Payload) -> &[String]) -> ArrayRef {
+    let mut builder = ListBuilder::new(StringBuilder::new());
+    for point in points {
+        for value in field(&point.payload) {
+            builder.values().append_value(value);
+        }
+        builder.append(true);
+    }
+    Arc::new(builder.finish())
+}
+
+fn from_record_batch(batch: &RecordBatch) -> Result<Vec<StoredPoint>> {
+    let ids: &StringArray = column(batch, "id")?;
+    let paths: &StringArray = column(batch, "path")?;
+    let hashes: &StringArray = column(batch, "hash")?;
+    let keys: &StringArray = column(batch, "key")?;
+    let urls: &StringArray = column(batch, "url")?;
+    let tags: &ListArray = column(batch, "tags")?;
+    let ratings: &StringArray = column(batch, "rating")?;
+    let source_folders: &StringArray = column(batch, "source_folder")?;
+    let widths: &UInt32Array = column(batch, "width")?;
+    let heights: &UInt32Array = column(batch, "height")?;
+    let indexed_ats: &Int64Array = column(batch, "indexed_at")?;
+    let all_paths: &ListArray = column(batch, "paths")?;
+    let first_seens: &Int64Array = column(batch, "first_seen")?;
+    let last_seens: &Int64Array = column(batch, "last_seen")?;
+    let vectors: &StringArray = column(batch, "vectors")?;
+
+    (0..batch.num_rows())
+        .map(|row| {
+            let id = ids.value(row).to_string();
+            let payload = Payload {
+                path: string(paths, row),
+                hash: string(hashes, row),
+                key: string(keys, row),
+                url: string(urls, row),
+                tags: string_list(tags, row),
+                rating: string(ratings, row),
+                source_folder: string(source_folders, row),
+                width: primitive(widths, row),
+                height: primitive(heights, row),
+                indexed_at: primitive(indexed_ats, row),
+                paths: string_list(all_paths, row),
+                first_seen: primitive(first_seens, row),
+                last_seen: primitive(last_seens, row),
+            };
+            let vectors = match string(vectors, row) {
+                Some(vectors) => serde_json::from_str(&vectors)
+                    .with_context(|| format!("Invalid vectors of point {id}"))?,
+                None => Default::default(),
+            };
+            Ok(StoredPoint {
+                id,
+                vectors,
+                payload,
+            })
+        })
+        .collect()
+}
+
+fn column<'a, T: Array + 'static>(batch: &'a RecordBatch, name: &str) -> Result<&'a T> {
+    batch
+        .column_by_name(name)
+        .and_then(|column| column.as_any().downcast_ref::<T>())
+        .with_context(|| format!("Dump has no column {name} of type {}", type_name::<T>()))
+}
+
+fn string(array: &StringArray, row: usize) -> Option<String> {
+    array.is_valid(row).then(|| array.value(row).to_string())
+}
+
+fn primitive<T: ArrowPrimitiveType>(array: &PrimitiveArray<T>, row: usize) -> Option<T::Native> {
+    array.is_valid(row).then(|| array.value(row))
+}
+
+fn string_list(array: &ListArray, row: usize) -> Vec<String> {
+    if array.is_null(row) {
+        return Vec::new();
+    }
+    array
+        .value(row)
+        .as_string::<i32>()
+        .iter()
+        .flatten()
+        .map(String::from)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use image_tager::{VectorData, VectorSpec, VectorStorage};
+    use qdrant_client::qdrant::{vectors::VectorsOptions, PointStruct};
+
+    use super::*;
+
+    fn point(id: &str, vectors: Vec<(&str, VectorData)>) -> StoredPoint {
+        StoredPoint {
+            id: id.to_string(),
+            vectors: vectors
+                .into_iter()
+                .map(|(name, vector)| (name.to_string(), vector))
+                .collect(),
+            payload: Payload {
+                path: Some(format!("/images/{id}.jpg")),
+                hash: Some(format!("{id}-hash")),
+                tags: vec!["solo".to_string(), ":,)".to_string()],
+                rating: Some("general".to_string()),
+                width: Some(640),
+                height: Some(480),
+                indexed_at: Some(1_700_000_000),
+                ..Default::default()
+            },
+        }
+    }
+
+    fn sample_points() -> Vec<StoredPoint> {
+        vec![
+            point(
+                "named",
+                vec![
+                    ("tags", VectorData::Dense(vec![0.25, 0.75, 0.0])),
+                    (
+                        "tags_sparse",
+                        VectorData::Sparse {
+                            indices: vec![1],
+                            values: vec![0.75],
+                        },
+                    ),
+                ],
+            ),
+            point(
+                "unnamed",
+                vec![("", VectorData::Dense(vec![0.5, 0.5, 0.0]))],
+            ),
+            point("missing", Vec::new()),
+        ]
+    }
+
+    /// Writes the sample points in two batches and reads them back two at a time.
+    fn round_trip(format: DumpFormat) -> Vec<StoredPoint> {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("dump");
+        let points = sample_points();
+        let mut writer = DumpWriter::create(&path, format).unwrap();
+        writer.write(&points[..2]).unwrap();
+        writer.write(&points[2..]).unwrap();
+        writer.finish().unwrap();
+
+        let mut reader = DumpReader::open(&path, format, 2).unwrap();
+        assert_eq!(reader.len(), 3);
+        let mut read = Vec::new();
+        while let Some(batch) = reader.next_batch().unwrap() {
+            assert!(batch.len() <= 2);
+            read.extend(batch);
+        }
+        read
+    }
+
+    fn to_json(points: &[StoredPoint]) -> serde_json::Value {
+        serde_json::to_value(points).unwrap()
+    }
+
+    #[test]
+    fn jsonl_round_trip() {
+        assert_eq!(
+            to_json(&round_trip(DumpFormat::Jsonl)),
+            to_json(&sample_points())
+        );
+    }
+
+    #[test]
+    fn parquet_round_trip() {
+        assert_eq!(
+            to_json(&round_trip(DumpFormat::Parquet)),
+            to_json(&sample_points())
+        );
+    }
+
+    #[test]
+    fn dumped_points_validate_against_collection_vectors() {
+        let points = round_trip(DumpFormat::Parquet);
+        let sparse = VectorSpec::new("tags_sparse", VectorStorage::Sparse { floor: 0.0 });
+        let named = [
+            VectorSpec::new("tags", VectorStorage::Dense { size: 3 }),
+            sparse.clone(),
+        ];
+        let unnamed = [VectorSpec::new("", VectorStorage::Dense { size: 3 })];
+
+        points[0].validate(&named).unwrap();
+        points[1].validate(&unnamed).unwrap();
+        assert!(points[0].validate(&unnamed).is_err());
+        assert!(points[1].validate(&named).is_err());
+        assert!(points[2].validate(&named).is_err());
+        let resized = [
+            VectorSpec::new("tags", VectorStorage::Dense { size: 4 }),
+            sparse,
+        ];
+        assert!(points[0].validate(&resized).is_err());
+    }
+
+    #[test]
+    fn dumped_points_convert_to_qdrant_points() {
+        let mut points = round_trip(DumpFormat::Jsonl).into_iter();
+        let vectors = |point: StoredPoint| {
+            PointStruct::try_from(point)
+                .unwrap()
+                .vectors
+                .and_then(|vectors| vectors.vectors_options)
+        };
+
+        match vectors(points.next().unwrap()) {
+            Some(VectorsOptions::Vectors(named)) => {
+                let mut names: Vec<_> = named.vectors.keys().cloned().collect();
+                names.sort_unstable();
+                assert_eq!(names, ["tags", "tags_sparse"]);
+                assert!(named.vectors["tags_sparse"].indices.is_some());
+            }
+            other => panic!("expected named vectors, got {other:?}"),
+        }
+        assert!(matches!(
+            vectors(points.next().unwrap()),
+            Some(VectorsOptions::Vector(_))
+        ));
+
+        let mut mixed = sample_points().remove(0);
+        mixed
+            .vectors
+            .insert(String::new(), VectorData::Dense(vec![0.0; 3]));
+        assert!(PointStruct::try_from(mixed).is_err());
+    }
+}
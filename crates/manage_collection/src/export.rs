@@ -0,0 +1,77 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::Args;
+use indicatif::ProgressBar;
+
+use image_tager::{progress_style, Config as AppConfig, QdrantWrapper};
+
+use crate::dump::{DumpFormat, DumpWriter};
+
+#[derive(Args)]
+pub struct ExportArgs {
+    output: PathBuf,
+    /// Dump format; inferred from the output extension when omitted
+    #[arg(short, long)]
+    format: Option<DumpFormat>,
+    /// Include the vectors of every point
+    #[arg(long)]
+    with_vectors: bool,
+    /// Collection to export instead of the configured one
+    #[arg(short, long)]
+    collection: Option<String>,
+    #[arg(short, long, default_value_t = 256)]
+    page_size: u32,
+    /// Point id to resume scrolling from
+    #[arg(long)]
+    offset: Option<String>,
+}
+
+pub struct Exporter {
+    qdrant_client: QdrantWrapper,
+    app_config: AppConfig,
+}
+
+impl Exporter {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            qdrant_client: QdrantWrapper::new()?,
+            app_config: AppConfig::new()?,
+        })
+    }
+
+    pub async fn process(&self, args: &ExportArgs) -> Result<()> {
+        let collection = args
+            .collection
+            .as_deref()
+            .unwrap_or(&self.app_config.collection_name);
+        let format = DumpFormat::resolve(args.format, &args.output);
+        let mut writer = DumpWriter::create(&args.output, format)?;
+
+        let progress_bar = ProgressBar::new(self.qdrant_client.count_points(collection).await?);
+        progress_bar.set_style(progress_style()?);
+
+        let mut offset = args.offset.clone();
+        loop {
+            let page = self
+                .qdrant_client
+                .scroll_points(
+                    collection,
+                    offset.as_deref(),
+                    args.page_size,
+                    args.with_vectors,
+                )
+                .await?;
+            writer.write(&page.points)?;
+            progress_bar.inc(page.points.len() as u64);
+
+            match page.next_offset {
+                Some(next) => offset = Some(next),
+                None => break,
+            }
+        }
+
+        progress_bar.finish();
+        writer.finish()
+    }
+}
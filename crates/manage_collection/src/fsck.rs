@@ -0,0 +1,272 @@
+use std::{
+    collections::{HashMap, HashSet},
+    path::{Path, PathBuf},
+};
+
+use anyhow::{Context, Result};
+use clap::Args;
+use indicatif::ProgressBar;
+use qdrant_client::Payload as QdrantPayload;
+use serde::Serialize;
+
+use image_tager::{
+    key_hash, progress_style, Config as AppConfig, ObjectStorage, Payload, QdrantWrapper, Storage,
+    StorageLayout, UploadOptions,
+};
+
+#[derive(Args)]
+pub struct FsckArgs {
+    /// Download every object and compare its blake3 hash with the payload
+    #[arg(long)]
+    verify_content: bool,
+    /// Re-upload missing or corrupt objects from their source paths and delete orphans;
+    /// objects under a key named after another hash are uploaded to the right key instead
+    #[arg(long)]
+    repair: bool,
+    /// Write the report as JSON to this file instead of printing a summary
+    #[arg(long)]
+    report: Option<PathBuf>,
+    /// Collection to check instead of the configured one
+    #[arg(short, long)]
+    collection: Option<String>,
+}
+
+#[derive(Serialize, Default)]
+struct FsckReport {
+    collection: String,
+    points_checked: u64,
+    objects_listed: u64,
+    /// Points whose object is absent from the bucket.
+    missing: Vec<PointIssue>,
+    /// Objects no point in any collection references.
+    orphans: Vec<String>,
+    /// Points whose object key or content doesn't match the payload hash.
+    mismatches: Vec<PointIssue>,
+    /// Object keys re-uploaded or deleted by `--repair`.
+    repaired: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct PointIssue {
+    point_id: String,
+    key: Option<String>,
+    expected_hash: Option<String>,
+    actual_hash: Option<String>,
+    /// Payload of the point, to restore the object from its source paths.
+    #[serde(skip)]
+    payload: Payload,
+}
+
+pub struct Fsck {
+    qdrant_client: QdrantWrapper,
+    storage: Storage,
+    app_config: AppConfig,
+    layout: StorageLayout,
+}
+
+impl Fsck {
+    pub fn new() -> Result<Self> {
+        let app_config = AppConfig::new()?;
+        Ok(Self {
+            qdrant_client: QdrantWrapper::new()?,
+            storage: Storage::new()?,
+            layout: StorageLayout::new(&app_config),
+            app_config,
+        })
+    }
+
+    pub async fn process(&self, args: &FsckArgs) -> Result<()> {
+        let collection = args
+            .collection
+            .as_deref()
+            .unwrap_or(&self.app_config.collection_name);
+        let mut report = FsckReport {
+            collection: collection.to_string(),
+            ..Default::default()
+        };
+
+        let objects: HashSet<String> = self.storage.list(None).await?.into_iter().collect();
+        report.objects_listed = objects.len() as u64;
+
+        // Older versions behind an alias keep their objects alive too
+        let resolved = self
+            .qdrant_client
+            .alias_target(collection)
+            .await?
+            .unwrap_or_else(|| collection.to_string());
+        let mut referenced = HashSet::new();
+        let mut payloads = None;
+        for other in self.qdrant_client.list_collections().await? {
+            let other_payloads = self.qdrant_client.all_payloads(&other).await?;
+            referenced.extend(
+                other_payloads
+                    .values()
+                    .filter_map(|payload| payload.object_key().map(String::from)),
+            );
+            if other == resolved {
+                payloads = Some(other_payloads);
+            }
+        }
+        let payloads = payloads.with_context(|| format!("Collection {collection} not found"))?;
+
+        report.points_checked = payloads.len() as u64;
+        let progress_bar = ProgressBar::new(payloads.len() as u64);
+        progress_bar.set_style(progress_style()?);
+        for (id, payload) in &payloads {
+            self.check_point(id, payload, &objects, args.verify_content, &mut report)
+                .await?;
+            progress_bar.inc(1);
+        }
+        progress_bar.finish();
+
+        report.orphans = objects.difference(&referenced).cloned().collect();
+        report.orphans.sort_unstable();
+
+        if args.repair {
+            self.repair(&resolved, &mut report).await?;
+        }
+
+        match &args.report {
+            Some(path) => std::fs::write(path, serde_json::to_vec_pretty(&report)?)
+                .context("Failed to write fsck report")?,
+            None => print_summary(&report),
+        }
+        Ok(())
+    }
+
+    async fn check_point(
+        &self,
+        id: &str,
+        payload: &Payload,
+        objects: &HashSet<String>,
+        verify_content: bool,
+        report: &mut FsckReport,
+    ) -> Result<()> {
+        // Images indexed in place have no object to check
+        if !payload.is_stored() {
+            return Ok(());
+        }
+        let issue = |actual_hash| PointIssue {
+            point_id: id.to_string(),
+            key: payload.object_key().map(String::from),
+            expected_hash: payload.hash.clone(),
+            actual_hash,
+            payload: payload.clone(),
+        };
+
+        let Some(key) = payload.object_key().filter(|key| objects.contains(*key)) else {
+            report.missing.push(issue(None));
+            return Ok(());
+        };
+
+        let key_hash = key_hash(key);
+        if payload.hash.as_deref() != Some(key_hash) {
+            report.mismatches.push(issue(Some(key_hash.to_string())));
+        } else if verify_content {
+            let data = self.storage.get(key).await?;
+            let actual = blake3::hash(&data).to_string();
+            if payload.hash.as_deref() != Some(actual.as_str()) {
+                report.mismatches.push(issue(Some(actual)));
+            }
+        }
+        Ok(())
+    }
+
+    async fn repair(&self, collection: &str, report: &mut FsckReport) -> Result<()> {
+        for issue in report.missing.iter().chain(&report.mismatches) {
+            let (Some(key), Some(hash)) = (&issue.key, &issue.expected_hash) else {
+                continue;
+            };
+            let Some((source, data)) = find_source(&issue.payload.source_paths(), hash)? else {
+                continue;
+            };
+            // Re-uploading under a key named after another hash would fix nothing
+            let target = if key_hash(key) == hash {
+                key.clone()
+            } else {
+                self.layout.key_for_path(hash, &source)?
+            };
+            self.storage
+                .put(&target, &data, &upload_options(&issue.payload, &source))
+                .await?;
+            if target != *key {
+                let ids = std::slice::from_ref(&issue.point_id);
+                self.qdrant_client
+                    .update_payload(
+                        collection,
+                        ids,
+                        QdrantPayload::from([("key", target.as_str().into())]),
+                    )
+                    .await?;
+                self.qdrant_client
+                    .delete_payload_keys(collection, ids, &["url"])
+                    .await?;
+            }
+            report.repaired.push(target);
+        }
+        for key in &report.orphans {
+            self.storage.delete(key).await?;
+            report.repaired.push(key.clone());
+        }
+        Ok(())
+    }
+}
+
+/// The first source file that still hashes to `hash`, with its contents.
+fn find_source(sources: &[PathBuf], hash: &str) -> Result<Option<(PathBuf, Vec<u8>)>> {
+    for source in sources.iter().filter(|source| source.is_file()) {
+        let data = std::fs::read(source)?;
+        if blake3::hash(&data).to_string() == hash {
+            return Ok(Some((source.clone(), data)));
+        }
+    }
+    Ok(None)
+}
+
+/// The object metadata `add_image` uploads with, as far as the payload records it; the top
+/// tags and the model id aren't kept in the payload.
+fn upload_options(payload: &Payload, source: &Path) -> UploadOptions {
+    let mut metadata = HashMap::new();
+    if let Some(name) = source.file_name() {
+        metadata.insert("filename".to_string(), name.to_string_lossy().into_owned());
+    }
+    if let Some(width) = payload.width {
+        metadata.insert("width".to_string(), width.to_string());
+    }
+    if let Some(height) = payload.height {
+        metadata.insert("height".to_string(), height.to_string());
+    }
+    UploadOptions {
+        metadata,
+        ..Default::default()
+    }
+}
+
+fn print_summary(report: &FsckReport) {
+    println!(
+        "Checked {} points against {} objects in {}",
+        report.points_checked, report.objects_listed, report.collection
+    );
+    for issue in &report.missing {
+        println!(
+            "missing\t{}\t{}",
+            issue.point_id,
+            issue.key.as_deref().unwrap_or("-")
+        );
+    }
+    for issue in &report.mismatches {
+        println!(
+            "mismatch\t{}\t{}\texpected {} got {}",
+            issue.point_id,
+            issue.key.as_deref().unwrap_or("-"),
+            issue.expected_hash.as_deref().unwrap_or("-"),
+            issue.actual_hash.as_deref().unwrap_or("-")
+        );
+    }
+    for key in &report.orphans {
+        println!("orphan\t{key}");
+    }
+    for key in &report.repaired {
+        println!("repaired\t{key}");
+    }
+}
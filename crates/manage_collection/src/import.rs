@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use clap::Args;
+use indicatif::ProgressBar;
+use qdrant_client::qdrant::PointStruct;
+
+use image_tager::{progress_style, Config as AppConfig, QdrantWrapper, StoredPoint, VectorSpec};
+
+use crate::dump::{DumpFormat, DumpReader};
+
+#[derive(Args)]
+pub struct ImportArgs {
+    input: PathBuf,
+    /// Dump format; inferred from the input extension when omitted
+    #[arg(short, long)]
+    format: Option<DumpFormat>,
+    /// Collection to import into instead of the configured one; created if missing
+    #[arg(short, long)]
+    collection: Option<String>,
+    #[arg(short, long, default_value_t = 256)]
+    batch_size: usize,
+}
+
+pub struct Importer {
+    qdrant_client: QdrantWrapper,
+    app_config: AppConfig,
+}
+
+impl Importer {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            qdrant_client: QdrantWrapper::new()?,
+            app_config: AppConfig::new()?,
+        })
+    }
+
+    pub async fn process(&self, args: &ImportArgs) -> Result<()> {
+        let requested = args
+            .collection
+            .as_deref()
+            .unwrap_or(&self.app_config.collection_name);
+        // Import into the version behind an alias rather than next to it
+        let collection = &self
+            .qdrant_client
+            .alias_target(requested)
+            .await?
+            .unwrap_or_else(|| requested.to_string());
+        let format = DumpFormat::resolve(args.format, &args.input);
+        let mut reader = DumpReader::open(&args.input, format, args.batch_size)?;
+
+        let progress_bar = ProgressBar::new(reader.len());
+        progress_bar.set_style(progress_style()?);
+
+        let mut specs: Option<Vec<VectorSpec>> = None;
+        while let Some(points) = reader.next_batch()? {
+            let specs = match &mut specs {
+                Some(specs) => specs,
+                None => specs.insert(self.target_vectors(collection, &points).await?),
+            };
+            for point in &points {
+                point
+                    .validate(specs)
+                    .with_context(|| format!("Dump doesn't fit collection {collection}"))?;
+            }
+
+            let count = points.len() as u64;
+            let points = points
+                .into_iter()
+                .map(PointStruct::try_from)
+                .collect::<Result<Vec<_>>>()?;
+            self.qdrant_client.add_points(collection, points).await?;
+            progress_bar.inc(count);
+        }
+
+        progress_bar.finish();
+        Ok(())
+    }
+
+    /// Vectors of the target collection, creating it from the first dumped point if missing.
+    async fn target_vectors(
+        &self,
+        collection: &str,
+        points: &[StoredPoint],
+    ) -> Result<Vec<VectorSpec>> {
+        let collections = self.qdrant_client.list_collections().await?;
+        if collections.iter().any(|c| c == collection) {
+            return self.qdrant_client.collection_vectors(collection).await;
+        }
+
+        let specs = points[0].vector_specs();
+        anyhow::ensure!(
+            !specs.is_empty(),
+            "Dump has no vectors; export it with --with-vectors"
+        );
+        self.qdrant_client
+            .create_collection(collection, &specs, &self.app_config.collection_options())
+            .await?;
+        Ok(specs)
+    }
+}
@@ -0,0 +1,58 @@
+use anyhow::Result;
+use clap::{Parser, Subcommand};
+
+use crate::delete::{DeleteArgs, Deleter};
+use crate::export::{ExportArgs, Exporter};
+use crate::fsck::{Fsck, FsckArgs};
+use crate::import::{ImportArgs, Importer};
+use crate::migrate::{KeyMigrator, MigrateArgs};
+use crate::snapshot::{SnapshotArgs, SnapshotManager};
+use crate::versions::{VersionManager, VersionsArgs};
+
+mod delete;
+mod dump;
+mod export;
+mod fsck;
+mod import;
+mod migrate;
+mod snapshot;
+mod versions;
+
+#[derive(Parser)]
+#[command(version, about, long_about = None)]
+struct CliConfig {
+    #[command(subcommand)]
+    command: Command,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Dump ids, payloads and optionally vectors of every point
+    Export(ExportArgs),
+    /// Upsert the points of a dump into a new or existing collection
+    Import(ImportArgs),
+    /// Create, list, download and restore collection snapshots
+    Snapshot(SnapshotArgs),
+    /// Inspect, roll back and prune the collection versions behind the alias
+    Versions(VersionsArgs),
+    /// Remove images from the collection and their objects from storage
+    Delete(DeleteArgs),
+    /// Check that points and stored objects agree, optionally repairing them
+    Fsck(FsckArgs),
+    /// Move stored objects to the configured key layout and update the points referencing them
+    MigrateKeys(MigrateArgs),
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let config = CliConfig::parse();
+    match &config.command {
+        Command::Export(args) => Exporter::new()?.process(args).await,
+        Command::Import(args) => Importer::new()?.process(args).await,
+        Command::Snapshot(args) => SnapshotManager::new()?.process(args).await,
+        Command::Versions(args) => VersionManager::new()?.process(args).await,
+        Command::Delete(args) => Deleter::new()?.process(args).await,
+        Command::Fsck(args) => Fsck::new()?.process(args).await,
+        Command::MigrateKeys(args) => KeyMigrator::new()?.process(args).await,
+    }
+}
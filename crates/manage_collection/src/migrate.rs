@@ -0,0 +1,127 @@
+use std::collections::{BTreeMap, HashSet};
+
+use anyhow::Result;
+use clap::Args;
+use indicatif::ProgressBar;
+use qdrant_client::Payload as QdrantPayload;
+
+use image_tager::{
+    progress_style, Config as AppConfig, ObjectStorage, QdrantWrapper, Storage, StorageLayout,
+};
+
+#[derive(Args)]
+pub struct MigrateArgs {
+    /// List the objects that would move without changing anything
+    #[arg(long)]
+    dry_run: bool,
+}
+
+/// Points of one collection whose object moves to a new key.
+struct PointMove {
+    collection: String,
+    id: String,
+    new_key: String,
+}
+
+/// Moves objects to the keys of the configured storage layout and stores the new key in every
+/// point referencing them, across all collections, replacing URLs written by older versions.
+pub struct KeyMigrator {
+    qdrant_client: QdrantWrapper,
+    storage: Storage,
+    layout: StorageLayout,
+}
+
+impl KeyMigrator {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            qdrant_client: QdrantWrapper::new()?,
+            storage: Storage::new()?,
+            layout: StorageLayout::new(&AppConfig::new()?),
+        })
+    }
+
+    pub async fn process(&self, args: &MigrateArgs) -> Result<()> {
+        let mut objects = BTreeMap::new();
+        let mut point_moves = Vec::new();
+        let mut kept = HashSet::new();
+        for collection in self.qdrant_client.list_collections().await? {
+            for (id, payload) in self.qdrant_client.all_payloads(&collection).await? {
+                // Images indexed in place have no object to move
+                let Some(key) = payload.object_key() else {
+                    continue;
+                };
+                let new_key = match self.layout.normalize_key(key) {
+                    Ok(new_key) => new_key,
+                    Err(e) => {
+                        eprintln!("Skipping point {id} in {collection}: {e}");
+                        kept.insert(key.to_string());
+                        continue;
+                    }
+                };
+                if payload.key.as_deref() == Some(new_key.as_str()) && payload.url.is_none() {
+                    kept.insert(new_key);
+                    continue;
+                }
+                if key != new_key {
+                    objects.insert(key.to_string(), new_key.clone());
+                }
+                point_moves.push(PointMove {
+                    collection: collection.clone(),
+                    id,
+                    new_key,
+                });
+            }
+        }
+
+        if args.dry_run {
+            for (key, new_key) in &objects {
+                println!("{key} -> {new_key}");
+            }
+            println!(
+                "Would move {} objects and update {} points",
+                objects.len(),
+                point_moves.len()
+            );
+            return Ok(());
+        }
+
+        let existing: HashSet<String> = self.storage.list(None).await?.into_iter().collect();
+        let progress_bar = ProgressBar::new(objects.len() as u64);
+        progress_bar.set_style(progress_style()?);
+        for (key, new_key) in &objects {
+            if !existing.contains(new_key) {
+                self.storage.copy(key, new_key).await?;
+            }
+            progress_bar.inc(1);
+        }
+        progress_bar.finish();
+
+        for point_move in &point_moves {
+            let ids = std::slice::from_ref(&point_move.id);
+            self.qdrant_client
+                .update_payload(
+                    &point_move.collection,
+                    ids,
+                    QdrantPayload::from([("key", point_move.new_key.as_str().into())]),
+                )
+                .await?;
+            self.qdrant_client
+                .delete_payload_keys(&point_move.collection, ids, &["url"])
+                .await?;
+        }
+
+        // Old keys go only once no point anywhere refers to them
+        let new_keys: HashSet<&String> = objects.values().collect();
+        for key in objects.keys() {
+            if !new_keys.contains(key) && !kept.contains(key) {
+                self.storage.delete(key).await?;
+            }
+        }
+        println!(
+            "Moved {} objects and updated {} points",
+            objects.len(),
+            point_moves.len()
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,103 @@
+use std::path::PathBuf;
+
+use anyhow::Result;
+use clap::{Args, Subcommand};
+
+use image_tager::{Config as AppConfig, QdrantWrapper, SnapshotInfo};
+
+#[derive(Args)]
+pub struct SnapshotArgs {
+    #[command(subcommand)]
+    action: SnapshotAction,
+    /// Collection to operate on instead of the configured one
+    #[arg(short, long, global = true)]
+    collection: Option<String>,
+}
+
+#[derive(Subcommand)]
+enum SnapshotAction {
+    /// Create a snapshot on the server
+    Create,
+    /// List snapshots, newest first
+    List {
+        /// Print one JSON object per snapshot
+        #[arg(long)]
+        json: bool,
+    },
+    /// Download a snapshot to a local file
+    Download {
+        output: PathBuf,
+        /// Snapshot to download; defaults to the newest
+        #[arg(short, long)]
+        name: Option<String>,
+    },
+    /// Restore the collection from a local snapshot file
+    Restore { input: PathBuf },
+}
+
+pub struct SnapshotManager {
+    qdrant_client: QdrantWrapper,
+    app_config: AppConfig,
+}
+
+impl SnapshotManager {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            qdrant_client: QdrantWrapper::new()?,
+            app_config: AppConfig::new()?,
+        })
+    }
+
+    pub async fn process(&self, args: &SnapshotArgs) -> Result<()> {
+        let requested = args
+            .collection
+            .as_deref()
+            .unwrap_or(&self.app_config.collection_name);
+        // Snapshots belong to the version behind an alias, not to the alias
+        let collection = &self
+            .qdrant_client
+            .alias_target(requested)
+            .await?
+            .unwrap_or_else(|| requested.to_string());
+
+        match &args.action {
+            SnapshotAction::Create => {
+                let snapshot = self.qdrant_client.create_snapshot(collection).await?;
+                print_snapshot(&snapshot);
+            }
+            SnapshotAction::List { json } => {
+                for snapshot in self.qdrant_client.list_snapshots(collection).await? {
+                    if *json {
+                        println!("{}", serde_json::to_string(&snapshot)?);
+                    } else {
+                        print_snapshot(&snapshot);
+                    }
+                }
+            }
+            SnapshotAction::Download { output, name } => {
+                self.qdrant_client
+                    .download_snapshot(collection, name.as_deref(), output)
+                    .await?;
+                println!("Downloaded to {}", output.display());
+            }
+            SnapshotAction::Restore { input } => {
+                self.qdrant_client
+                    .restore_snapshot(collection, input)
+                    .await?;
+                println!("Restored {collection} from {}", input.display());
+            }
+        }
+        Ok(())
+    }
+}
+
+fn print_snapshot(snapshot: &SnapshotInfo) {
+    println!(
+        "{}\t{} bytes\t{}",
+        snapshot.name,
+        snapshot.size,
+        snapshot
+            .created_at
+            .map_or_else(|| "-".to_string(), |t| t.to_string())
+    );
+}
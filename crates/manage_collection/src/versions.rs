@@ -0,0 +1,93 @@
+use anyhow::{Context, Result};
+use clap::{Args, Subcommand};
+
+use image_tager::{Config as AppConfig, QdrantWrapper};
+
+#[derive(Args)]
+pub struct VersionsArgs {
+    #[command(subcommand)]
+    action: VersionsAction,
+}
+
+#[derive(Subcommand)]
+enum VersionsAction {
+    /// List collection versions, marking the one the alias points to
+    List,
+    /// Point the alias back at the previous version
+    Rollback {
+        /// Version to switch to instead of the one before the current
+        #[arg(long)]
+        to: Option<String>,
+    },
+    /// Delete versions older than the current one, keeping a few for rollback
+    Prune {
+        #[arg(short, long, default_value_t = 1)]
+        keep: usize,
+    },
+}
+
+pub struct VersionManager {
+    qdrant_client: QdrantWrapper,
+    app_config: AppConfig,
+}
+
+impl VersionManager {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            qdrant_client: QdrantWrapper::new()?,
+            app_config: AppConfig::new()?,
+        })
+    }
+
+    pub async fn process(&self, args: &VersionsArgs) -> Result<()> {
+        let alias = &self.app_config.collection_name;
+        let versions = self.qdrant_client.collection_versions(alias).await?;
+        let current = self.qdrant_client.alias_target(alias).await?;
+
+        match &args.action {
+            VersionsAction::List => {
+                for version in &versions {
+                    let marker = if current.as_ref() == Some(version) {
+                        "*"
+                    } else {
+                        " "
+                    };
+                    println!("{marker} {version}");
+                }
+            }
+            VersionsAction::Rollback { to } => {
+                let target = match to {
+                    Some(to) => {
+                        anyhow::ensure!(versions.contains(to), "{to} is not a version of {alias}");
+                        to.clone()
+                    }
+                    None => {
+                        let position = current_position(alias, &versions, current.as_deref())?;
+                        versions[..position]
+                            .last()
+                            .context("No earlier version to roll back to")?
+                            .clone()
+                    }
+                };
+                self.qdrant_client.switch_alias(alias, &target).await?;
+                println!("Switched {alias} to {target}");
+            }
+            VersionsAction::Prune { keep } => {
+                let position = current_position(alias, &versions, current.as_deref())?;
+                for version in &versions[..position.saturating_sub(*keep)] {
+                    self.qdrant_client.delete_collection(version).await?;
+                    println!("Deleted {version}");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn current_position(alias: &str, versions: &[String], current: Option<&str>) -> Result<usize> {
+    let current = current.with_context(|| format!("{alias} is not an alias"))?;
+    versions
+        .iter()
+        .position(|version| version == current)
+        .with_context(|| format!("{current} is not a version of {alias}"))
+}
@@ -1,3 +1,3 @@
-pub use wd_tagger::Model as WdTagger;
+pub use wd_tagger::{Model as WdTagger, Tag, TagCategory};
 
 mod wd_tagger;
@@ -1,3 +1,5 @@
+use std::path::Path;
+
 use anyhow::{Context, Result};
 use hf_hub::api::sync::Api;
 use image::{imageops, Rgb, RgbImage};
@@ -7,10 +9,35 @@ use ort::Session;
 
 const MODEL_NAME: &str = "SmilingWolf/wd-swinv2-tagger-v3";
 
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TagCategory {
+    General,
+    Character,
+    Rating,
+    Other,
+}
+
+impl From<u32> for TagCategory {
+    fn from(category: u32) -> Self {
+        match category {
+            0 => Self::General,
+            4 => Self::Character,
+            9 => Self::Rating,
+            _ => Self::Other,
+        }
+    }
+}
+
+pub struct Tag {
+    pub name: String,
+    pub category: TagCategory,
+}
+
 pub struct Model {
     session: Session,
     pub target_size: u32,
     pub output_size: u32,
+    pub tags: Vec<Tag>,
     input_name: String,
     output_name: String,
 }
@@ -18,10 +45,12 @@ pub struct Model {
 impl Model {
     pub fn new(device_id: i32, num_threads: usize) -> Result<Self> {
         let api = Api::new().context("Failed to initialize API")?;
-        let model_path = api
-            .model(MODEL_NAME.parse()?)
-            .get("model.onnx")
-            .context("Failed to get model")?;
+        let repo = api.model(MODEL_NAME.parse()?);
+        let model_path = repo.get("model.onnx").context("Failed to get model")?;
+        let tags_path = repo
+            .get("selected_tags.csv")
+            .context("Failed to get tag list")?;
+        let tags = load_tags(&tags_path)?;
 
         let session = Session::builder()?
             .with_execution_providers([ort::CUDAExecutionProvider::default()
@@ -47,6 +76,7 @@ impl Model {
             session,
             target_size,
             output_size,
+            tags,
             input_name,
             output_name,
         })
@@ -75,7 +105,7 @@ impl Model {
             Axis(0),
             &images.iter().map(ArrayBase::view).collect::<Vec<_>>(),
         )
-            .context("Failed to stack batch of images")?;
+        .context("Failed to stack batch of images")?;
         let outputs = self
             .session
             .run_async(ort::inputs![self.input_name.clone() => batch.view()]?)
@@ -92,6 +122,74 @@ impl Model {
 
         Ok(outputs)
     }
+
+    /// Hugging Face id of the model weights.
+    pub fn id(&self) -> &'static str {
+        MODEL_NAME
+    }
+
+    /// Names of the `count` highest scoring general and character tags, best first.
+    pub fn top_tags(&self, probabilities: &[f32], count: usize) -> Vec<String> {
+        let mut scored: Vec<_> = self
+            .tags
+            .iter()
+            .zip(probabilities)
+            .filter(|(tag, _)| {
+                matches!(tag.category, TagCategory::General | TagCategory::Character)
+            })
+            .collect();
+        scored.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+        scored
+            .into_iter()
+            .take(count)
+            .map(|(tag, _)| tag.name.clone())
+            .collect()
+    }
+
+    /// Names of the general and character tags scoring at least `threshold`.
+    pub fn tags_above(&self, probabilities: &[f32], threshold: f32) -> Vec<String> {
+        self.tags
+            .iter()
+            .zip(probabilities)
+            .filter(|(tag, &p)| {
+                matches!(tag.category, TagCategory::General | TagCategory::Character)
+                    && p >= threshold
+            })
+            .map(|(tag, _)| tag.name.clone())
+            .collect()
+    }
+
+    /// Name of the highest scoring rating tag.
+    pub fn rating(&self, probabilities: &[f32]) -> Option<&str> {
+        self.tags
+            .iter()
+            .zip(probabilities)
+            .filter(|(tag, _)| tag.category == TagCategory::Rating)
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .map(|(tag, _)| tag.name.as_str())
+    }
+}
+
+fn load_tags(path: &Path) -> Result<Vec<Tag>> {
+    parse_tags(&std::fs::read_to_string(path).context("Failed to read tag list")?)
+}
+
+fn parse_tags(csv: &str) -> Result<Vec<Tag>> {
+    // Rows are `tag_id,name,category,count`; names may themselves contain commas.
+    csv.lines()
+        .skip(1)
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let malformed = || format!("Malformed tag row: {line}");
+            let (_, rest) = line.split_once(',').with_context(malformed)?;
+            let (rest, _) = rest.rsplit_once(',').with_context(malformed)?;
+            let (name, category) = rest.rsplit_once(',').with_context(malformed)?;
+            Ok(Tag {
+                name: name.to_string(),
+                category: category.parse::<u32>().with_context(malformed)?.into(),
+            })
+        })
+        .collect()
 }
 
 fn preprocess(image: &RgbImage, size: u32) -> Result<Array3<f32>> {
@@ -108,3 +206,45 @@ fn preprocess(image: &RgbImage, size: u32) -> Result<Array3<f32>> {
 
     Ok(tensor)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HEADER: &str = "tag_id,name,category,count\n";
+
+    #[test]
+    fn parse_tags_keeps_commas_in_names() {
+        let csv = format!("{HEADER}9999999,general,9,807489\n1,hatsune_miku,4,1\n2,:,),0,3\n");
+        let tags = parse_tags(&csv).unwrap();
+        let parsed: Vec<_> = tags
+            .iter()
+            .map(|tag| (tag.name.as_str(), tag.category))
+            .collect();
+        assert_eq!(
+            parsed,
+            [
+                ("general", TagCategory::Rating),
+                ("hatsune_miku", TagCategory::Character),
+                (":,)", TagCategory::General),
+            ]
+        );
+    }
+
+    #[test]
+    fn parse_tags_skips_header_and_blank_lines() {
+        let tags = parse_tags(&format!("{HEADER}\n1,solo,0,10\n\n")).unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].name, "solo");
+    }
+
+    #[test]
+    fn parse_tags_rejects_malformed_rows() {
+        for row in ["1,solo", "1,solo,general,10", "solo"] {
+            let err = parse_tags(&format!("{HEADER}{row}\n"))
+                .err()
+                .expect("malformed row accepted");
+            assert_eq!(err.to_string(), format!("Malformed tag row: {row}"));
+        }
+    }
+}
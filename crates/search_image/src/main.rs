@@ -1,18 +1,40 @@
 use std::path::{Path, PathBuf};
 
 use anyhow::{Context, Result};
-use clap::Parser;
+use clap::{Parser, ValueEnum};
 use image::ImageFormat;
-use image_tager::{progress_style, Config as AppConfig, Payload, QdrantWrapper, S3Client, SearchParams};
+use image_tager::{
+    progress_style, Config as AppConfig, Example, GroupBy, ObjectStorage, PayloadFilter, PointRef,
+    QdrantWrapper, SearchHit, SearchParams, SearchQuery, SearchStrategy, Storage, VectorSpec,
+    VectorStorage, SPARSE_TAGS_VECTOR, TAGS_VECTOR,
+};
 use indicatif::ProgressBar;
 use models::WdTagger;
+use serde::Serialize;
 use tokio::fs;
 use walkdir::WalkDir;
 
+/// Per-entry report listing every hit (or group of hits) with scores and payloads, one JSON
+/// object per line.
+const REPORT_FILE: &str = "results.jsonl";
+
+/// Subfolder of a query entry holding images to steer results away from.
+const NEGATIVE_DIR: &str = "negative";
+
+/// Entry name used when searching only by already-indexed images.
+const LIKE_ENTRY: &str = "like";
+/// Upper bound on the examples a single `--like` reference may expand to.
+const MAX_LIKE_MATCHES: usize = 64;
+/// Probability floor for sparse queries without `--sparse-floor`, the default tag threshold
+/// of `add_image`.
+const DEFAULT_SPARSE_FLOOR: f32 = 0.35;
+
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct CliConfig {
-    input: PathBuf,
+    /// Query image or folder of query folders; without it results go to ./output/like
+    #[arg(required_unless_present = "likes")]
+    input: Option<PathBuf>,
     output: Option<PathBuf>,
     #[arg(short, long, default_value_t = 100)]
     limit: usize,
@@ -30,49 +52,184 @@ struct CliConfig {
     exact: bool,
     #[arg(short, long, default_value_t = 32)]
     hnsw_ef: u64,
+    /// Named vector to query; an empty name selects the unnamed default vector, which is also
+    /// the default for collections that have one
+    #[arg(long)]
+    vector: Option<String>,
+    /// Query a sparse vector, keeping only tags with at least this probability; collections
+    /// without dense vectors are queried sparsely with a floor of 0.35 by default
+    #[arg(long)]
+    sparse_floor: Option<f32>,
+    /// Only return images with this tag (repeatable; all must match)
+    #[arg(long = "tag")]
+    tags: Vec<String>,
+    /// Only return images with this rating (repeatable; any may match)
+    #[arg(long = "rating")]
+    ratings: Vec<String>,
+    #[arg(long)]
+    source_folder: Option<String>,
+    #[arg(long)]
+    min_width: Option<u32>,
+    #[arg(long)]
+    min_height: Option<u32>,
+    /// Only return images indexed at or after this Unix timestamp
+    #[arg(long)]
+    indexed_after: Option<i64>,
+    /// Folder of images used as negative examples for every entry
+    #[arg(long)]
+    negative: Option<PathBuf>,
+    /// How examples are combined: average_vector or best_score
+    #[arg(long, default_value = "average_vector")]
+    strategy: SearchStrategy,
+    /// Group hits by this payload field, e.g. source_folder; --limit then counts groups
+    #[arg(long)]
+    group_by: Option<String>,
+    /// Maximum number of hits per group
+    #[arg(long, default_value_t = 3)]
+    group_size: u32,
+    /// Indexed image to use as an extra positive example: id:<point id>, hash:<blake3> or
+    /// path:<file name or source path> (repeatable)
+    #[arg(long = "like")]
+    likes: Vec<PointRef>,
+    /// How results without a stored object are placed in the output from their indexed files
+    #[arg(long, value_enum, default_value_t = LinkMode::Copy)]
+    link: LinkMode,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+enum LinkMode {
+    Copy,
+    Hardlink,
+    Symlink,
+}
+
+impl CliConfig {
+    fn payload_filter(&self) -> PayloadFilter {
+        PayloadFilter {
+            tags: self.tags.clone(),
+            ratings: self.ratings.clone(),
+            source_folder: self.source_folder.clone(),
+            min_width: self.min_width,
+            min_height: self.min_height,
+            indexed_after: self.indexed_after,
+        }
+    }
+
+    fn group_by(&self) -> Option<GroupBy> {
+        self.group_by.as_ref().map(|field| GroupBy {
+            field: field.clone(),
+            group_size: self.group_size,
+        })
+    }
+
+    /// Whether any images have to be tagged, as opposed to only referencing indexed ones.
+    fn needs_model(&self) -> bool {
+        self.input.is_some() || self.negative.is_some()
+    }
 }
 
 struct ImageSearcher {
     qdrant_client: QdrantWrapper,
-    s3_client: S3Client,
-    model: WdTagger,
+    /// `None` in index-only mode, where results are taken from the indexed files.
+    storage: Option<Storage>,
+    model: Option<WdTagger>,
     app_config: AppConfig,
 }
 
 impl ImageSearcher {
-    fn new(device_id: i32, num_threads: usize) -> Result<Self> {
+    fn new(config: &CliConfig) -> Result<Self> {
         let app_config = AppConfig::new()?;
         let qdrant_client = QdrantWrapper::new()?;
-        let s3_client = S3Client::new()?;
-        let model = WdTagger::new(device_id, num_threads)?;
+        let storage = Storage::from_config()?;
+        let model = config
+            .needs_model()
+            .then(|| WdTagger::new(config.device_id, config.num_threads))
+            .transpose()?;
 
         Ok(Self {
             qdrant_client,
-            s3_client,
+            storage,
             model,
             app_config,
         })
     }
 
     async fn process(&self, config: &CliConfig) -> Result<()> {
-        let input =
-            dunce::canonicalize(&config.input).context("Failed to canonicalize input path")?;
-        let output = self.get_output_path(&input, &config.output)?;
+        let input = config
+            .input
+            .as_ref()
+            .map(|input| dunce::canonicalize(input).context("Failed to canonicalize input path"))
+            .transpose()?;
+        let output = match &input {
+            Some(input) => self.get_output_path(input, &config.output)?,
+            None => config
+                .output
+                .clone()
+                .unwrap_or_else(|| PathBuf::from("output")),
+        };
         fs::create_dir_all(&output)
             .await
             .context("Failed to create output directory")?;
 
-        let input_entries = self.get_input_entries(&input)?;
+        // Pin the version behind the alias so a concurrent reindex can't mix results
+        let collection = self
+            .qdrant_client
+            .alias_target(&self.app_config.collection_name)
+            .await?
+            .unwrap_or_else(|| self.app_config.collection_name.clone());
 
-        for (entry, files) in input_entries {
-            let entry_output = output.join(&entry);
-            fs::create_dir_all(&entry_output)
-                .await
-                .context("Failed to create entry output directory")?;
+        let mut entries = match &input {
+            Some(input) => self.get_input_entries(input)?,
+            None => vec![QueryEntry {
+                name: LIKE_ENTRY.to_string(),
+                files: Vec::new(),
+                negatives: Vec::new(),
+            }],
+        };
+        if let Some(negative) = &config.negative {
+            let shared_negatives = list_images(negative);
+            for entry in &mut entries {
+                entry.negatives.extend(shared_negatives.iter().cloned());
+            }
+        }
 
-            self.process_entry(&entry, &files, &entry_output, config)
-                .await
-                .with_context(|| format!("Failed to process entry: {}", entry))?;
+        let mut queries = self.embed_entries(&entries).await?;
+        let liked = self.resolve_likes(&collection, &config.likes).await?;
+        for query in &mut queries {
+            query.positive.extend(liked.iter().cloned());
+        }
+        let params = self.search_params(config, &collection).await?;
+        match config.group_by() {
+            // Qdrant has no batch endpoint for grouped recommendations
+            Some(group_by) => {
+                for (entry, query) in entries.iter().zip(queries) {
+                    let mut groups = self
+                        .qdrant_client
+                        .search_groups(&collection, query, &params, &group_by)
+                        .await
+                        .with_context(|| format!("Failed to search entry: {}", entry.name))?;
+                    for group in &mut groups {
+                        self.presign_urls(&mut group.hits).await?;
+                    }
+                    let hits: Vec<SearchHit> = groups
+                        .iter()
+                        .flat_map(|group| group.hits.iter().cloned())
+                        .collect();
+                    self.save_entry_results(entry, &groups, &hits, &output, config)
+                        .await?;
+                }
+            }
+            None => {
+                let results = self
+                    .qdrant_client
+                    .search_points_batch(&collection, queries, &params)
+                    .await?;
+                for (entry, mut hits) in entries.iter().zip(results) {
+                    self.presign_urls(&mut hits).await?;
+                    self.save_entry_results(entry, &hits, &hits, &output, config)
+                        .await?;
+                }
+            }
         }
 
         Ok(())
@@ -89,13 +246,13 @@ impl ImageSearcher {
         }
     }
 
-    fn validate_single_image_input(&self, input: &Path) -> Result<Vec<(String, Vec<PathBuf>)>> {
+    fn validate_single_image_input(&self, input: &Path) -> Result<Vec<QueryEntry>> {
         anyhow::ensure!(
             ImageFormat::from_path(input).is_ok(),
             "Invalid image format"
         );
-        Ok(vec![(
-            input
+        Ok(vec![QueryEntry {
+            name: input
                 .parent()
                 .unwrap()
                 .file_name()
@@ -103,82 +260,219 @@ impl ImageSearcher {
                 .to_str()
                 .unwrap()
                 .to_string(),
-            vec![input.to_path_buf()],
-        )])
+            files: vec![input.to_path_buf()],
+            negatives: Vec::new(),
+        }])
     }
 
-    async fn process_entry(
+    async fn embed_entries(&self, entries: &[QueryEntry]) -> Result<Vec<SearchQuery>> {
+        let total = entries
+            .iter()
+            .map(|entry| entry.files.len() + entry.negatives.len())
+            .sum::<usize>();
+        let progress_bar = ProgressBar::new(total as u64);
+        progress_bar.set_style(progress_style()?);
+
+        let mut queries = Vec::with_capacity(entries.len());
+        for entry in entries {
+            progress_bar.set_message(entry.name.clone());
+            let query = self
+                .embed_entry(entry, &progress_bar)
+                .await
+                .with_context(|| format!("Failed to process entry: {}", entry.name))?;
+            queries.push(query);
+        }
+        progress_bar.finish();
+        Ok(queries)
+    }
+
+    async fn embed_entry(&self, entry: &QueryEntry, pb: &ProgressBar) -> Result<SearchQuery> {
+        let examples = |vectors: Vec<Vec<f32>>| vectors.into_iter().map(Example::Vector).collect();
+        Ok(SearchQuery {
+            positive: examples(self.process_images(&entry.files, pb).await?),
+            negative: examples(self.process_images(&entry.negatives, pb).await?),
+        })
+    }
+
+    async fn resolve_likes(&self, collection: &str, likes: &[PointRef]) -> Result<Vec<Example>> {
+        let mut examples = Vec::new();
+        for like in likes {
+            let ids = self
+                .qdrant_client
+                .resolve_point_ref(collection, like)
+                .await?;
+            anyhow::ensure!(!ids.is_empty(), "No indexed image matches {like:?}");
+            anyhow::ensure!(
+                ids.len() <= MAX_LIKE_MATCHES,
+                "{like:?} matches {} images, more than {MAX_LIKE_MATCHES}; use a more specific \
+                 reference",
+                ids.len()
+            );
+            examples.extend(ids.into_iter().map(Example::Point));
+        }
+        Ok(examples)
+    }
+
+    /// Replaces the URL in each hit's payload with a presigned one, so reports and
+    /// `--use_reqwest` downloads work against private buckets.
+    async fn presign_urls(&self, hits: &mut [SearchHit]) -> Result<()> {
+        let Some(storage) = &self.storage else {
+            return Ok(());
+        };
+        for hit in hits.iter_mut().filter(|hit| hit.payload.is_stored()) {
+            // Pin the key first, as it may have been derived from the URL being replaced
+            if let Some(key) = hit.payload.object_key().map(String::from) {
+                hit.payload.url = Some(storage.url(&key).await?);
+                hit.payload.key = Some(key);
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the report of one entry and downloads its hits into `<output>/<entry name>`.
+    async fn save_entry_results(
         &self,
-        tag: &str,
-        files: &[PathBuf],
+        entry: &QueryEntry,
+        report: &[impl Serialize],
+        hits: &[SearchHit],
         output: &Path,
         config: &CliConfig,
     ) -> Result<()> {
-        let progress_bar = ProgressBar::new(files.len() as u64);
-        progress_bar.set_style(progress_style()?);
-        progress_bar.set_message(tag.to_string());
+        let entry_output = output.join(&entry.name);
+        fs::create_dir_all(&entry_output)
+            .await
+            .context("Failed to create entry output directory")?;
 
-        let vectors = self.process_images(files, &progress_bar).await?;
-        let files_to_download = self
-            .search_similar_images(vectors, config.limit as u64, config.score_threshold, config.exact, config.hnsw_ef)
-            .await?;
+        let progress_bar = ProgressBar::new(hits.len() as u64);
+        progress_bar.set_style(progress_style()?);
+        progress_bar.set_message(entry.name.clone());
 
-        self.download_files(&files_to_download, output, &progress_bar, config.use_reqwest)
+        self.write_report(report, &entry_output).await?;
+        self.download_files(hits, &entry_output, &progress_bar, config)
             .await
+            .with_context(|| format!("Failed to download results of entry: {}", entry.name))
     }
 
     async fn process_images(&self, files: &[PathBuf], pb: &ProgressBar) -> Result<Vec<Vec<f32>>> {
         let mut vectors = Vec::new();
         for file in files {
+            let model = self
+                .model
+                .as_ref()
+                .context("No model loaded to tag images")?;
             let image = image::open(file)?.into_rgb8();
-            let vector = self.model.predict(&image).await?;
+            let vector = model.predict(&image).await?;
             vectors.push(vector);
             pb.inc(1);
         }
         Ok(vectors)
     }
 
-    async fn search_similar_images(
-        &self,
-        vectors: Vec<Vec<f32>>,
-        limit: u64,
-        score_threshold: f32,
-        exact: bool,
-        hnsw_ef: u64,
-    ) -> Result<Vec<Payload>> {
-        let params = SearchParams {
-            score_threshold,
-            exact,
-            hnsw_ef,
-            limit,
+    async fn search_params(&self, config: &CliConfig, collection: &str) -> Result<SearchParams> {
+        let existing = self.qdrant_client.collection_vectors(collection).await?;
+        Ok(SearchParams {
+            vector: self.vector_spec(config, &existing),
+            score_threshold: config.score_threshold,
+            exact: config.exact,
+            hnsw_ef: config.hnsw_ef,
+            limit: config.limit as u64,
+            filter: config.payload_filter(),
+            strategy: config.strategy,
+        })
+    }
+
+    /// The vector to query, defaulting to the one `existing` vectors of the collection offer.
+    fn vector_spec(&self, config: &CliConfig, existing: &[VectorSpec]) -> VectorSpec {
+        let default_name = if config.sparse_floor.is_some() {
+            SPARSE_TAGS_VECTOR
+        } else if existing.iter().any(|spec| spec.name.is_empty()) {
+            // Collections older than named vectors only have the unnamed default one
+            ""
+        } else if existing.iter().any(|spec| !spec.is_sparse()) {
+            TAGS_VECTOR
+        } else {
+            // Collections indexed with --sparse-only
+            SPARSE_TAGS_VECTOR
         };
-        self.qdrant_client
-            .search_points(&self.app_config.collection_name, vectors, &params)
+        let name = config.vector.as_deref().unwrap_or(default_name);
+        let sparse = config.sparse_floor.is_some()
+            || existing
+                .iter()
+                .any(|spec| spec.name == name && spec.is_sparse());
+        let storage = if sparse {
+            VectorStorage::Sparse {
+                floor: config.sparse_floor.unwrap_or(DEFAULT_SPARSE_FLOOR),
+            }
+        } else {
+            VectorStorage::Dense {
+                // Only used when creating collections, so unknown without a model
+                size: self
+                    .model
+                    .as_ref()
+                    .map_or(0, |model| model.output_size as u64),
+            }
+        };
+        VectorSpec::new(name, storage)
+    }
+
+    async fn write_report(&self, records: &[impl Serialize], output: &Path) -> Result<()> {
+        let mut report = String::new();
+        for record in records {
+            report.push_str(&serde_json::to_string(record)?);
+            report.push('\n');
+        }
+        fs::write(output.join(REPORT_FILE), report)
             .await
+            .context("Failed to write search report")
     }
 
     async fn download_files(
         &self,
-        files: &[Payload],
+        hits: &[SearchHit],
         output: &Path,
         progress_bar: &ProgressBar,
-        use_reqwest: bool,
+        config: &CliConfig,
     ) -> Result<()> {
-        for payload in files {
-            let path = output.join(&payload.path);
-            let data = if use_reqwest {
-                reqwest::get(&payload.url).await?.bytes().await?.to_vec()
-            } else {
-                self.s3_client.download_file(&payload.hash).await?
+        for hit in hits {
+            let payload = &hit.payload;
+            let Some(name) = payload.path.as_ref().or(payload.hash.as_ref()) else {
+                progress_bar.println(format!("Skipping point {} with incomplete payload", hit.id));
+                continue;
             };
+            let path = output.join(name);
             fs::create_dir_all(path.parent().unwrap()).await?;
-            fs::write(&path, data).await?;
+
+            match (&self.storage, payload.object_key()) {
+                (Some(storage), Some(key)) => {
+                    // Local storage hands out file URLs, which reqwest can't fetch
+                    let data = match (config.use_reqwest, &payload.url) {
+                        (true, Some(url)) if url.starts_with("http") => {
+                            reqwest::get(url).await?.bytes().await?.to_vec()
+                        }
+                        _ => storage.get(key).await?,
+                    };
+                    fs::write(&path, data).await?;
+                }
+                _ => {
+                    let Some(source) = payload.source_paths().into_iter().find(|p| p.is_file())
+                    else {
+                        progress_bar
+                            .println(format!("Skipping point {}: no source file left", hit.id));
+                        continue;
+                    };
+                    place_file(&source, &path, config.link)
+                        .await
+                        .with_context(|| {
+                            format!("Failed to place {} in the output", source.display())
+                        })?;
+                }
+            }
             progress_bar.inc(1);
         }
         Ok(())
     }
 
-    fn get_input_entries(&self, input: &Path) -> Result<Vec<(String, Vec<PathBuf>)>> {
+    fn get_input_entries(&self, input: &Path) -> Result<Vec<QueryEntry>> {
         if input.is_dir() {
             Ok(self.get_image_entries(input))
         } else {
@@ -186,32 +480,63 @@ impl ImageSearcher {
         }
     }
 
-    fn get_image_entries(&self, root_path: &Path) -> Vec<(String, Vec<PathBuf>)> {
+    fn get_image_entries(&self, root_path: &Path) -> Vec<QueryEntry> {
         WalkDir::new(root_path)
             .into_iter()
             .filter_map(Result::ok)
-            .filter(|e| e.file_type().is_dir())
+            .filter(|e| e.file_type().is_dir() && e.file_name() != NEGATIVE_DIR)
             .filter_map(|entry| {
                 let path = entry.path();
                 let folder_name = path.file_name()?.to_str()?.to_string();
 
-                let image_files: Vec<_> = path
-                    .read_dir()
-                    .ok()?
-                    .filter_map(Result::ok)
-                    .map(|file_entry| file_entry.path())
-                    .filter(|file_path| ImageFormat::from_path(file_path).is_ok())
-                    .collect();
-
-                (!image_files.is_empty()).then_some((folder_name, image_files))
+                let image_files = list_images(path);
+                (!image_files.is_empty()).then(|| QueryEntry {
+                    name: folder_name,
+                    files: image_files,
+                    negatives: list_images(&path.join(NEGATIVE_DIR)),
+                })
             })
             .collect()
     }
 }
 
+struct QueryEntry {
+    name: String,
+    files: Vec<PathBuf>,
+    negatives: Vec<PathBuf>,
+}
+
+fn list_images(dir: &Path) -> Vec<PathBuf> {
+    dir.read_dir()
+        .into_iter()
+        .flatten()
+        .filter_map(Result::ok)
+        .map(|file_entry| file_entry.path())
+        .filter(|file_path| ImageFormat::from_path(file_path).is_ok())
+        .collect()
+}
+
+/// Puts the indexed file `source` at `target`, replacing whatever is there.
+async fn place_file(source: &Path, target: &Path, mode: LinkMode) -> Result<()> {
+    if fs::symlink_metadata(target).await.is_ok() {
+        fs::remove_file(target).await?;
+    }
+    match mode {
+        LinkMode::Copy => {
+            fs::copy(source, target).await?;
+        }
+        LinkMode::Hardlink => fs::hard_link(source, target).await?,
+        #[cfg(unix)]
+        LinkMode::Symlink => fs::symlink(source, target).await?,
+        #[cfg(windows)]
+        LinkMode::Symlink => fs::symlink_file(source, target).await?,
+    }
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     let config = CliConfig::parse();
-    let searcher = ImageSearcher::new(config.device_id, config.num_threads)?;
+    let searcher = ImageSearcher::new(&config)?;
     searcher.process(&config).await
 }